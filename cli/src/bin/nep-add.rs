@@ -3,38 +3,126 @@
 use dotenv::dotenv;
 
 use nephrite4_common::proj;
+use nephrite4_common::error::*;
 //use nephrite4_common::util;
 
+use proj::ignore::Ignore;
 use proj::manifest;
+use proj::Project;
 
 use std::io::prelude::*;
 use std::io;
+use std::fs;
+
+// file argument as is, directory expand to files not ignored & not added
+fn expand(project: &Project, manifest: Option<&manifest::Manifest>, arg: &str)
+          -> Result<Vec<String>> {
+    let rdir = project.rdir(arg)?;
+
+    if !project.path(&rdir).is_dir() {
+        return Ok(vec![project.rpath(arg)?])
+    }
+
+    let mut ignore = Ignore::for_dir(&project.root, &rdir)?;
+
+    Ok(ignore.walk(&project.root, &rdir)?.into_iter()
+       .filter(|r| !manifest.is_some_and(|m| m.anno_map.contains_key(r)))
+       .collect())
+}
 
 fn main() {
     dotenv().ok();
 
     env_logger::init();
 
-    let files = std::env::args_os().skip(1).
+    let args = std::env::args_os().skip(1).
         map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
 
+    let wait = args.iter().any(|a| a == "--wait");
+    let dry_run = args.iter().any(|a| a == "--dry-run" || a == "-n");
+    let files: Vec<&String> = args.iter()
+        .filter(|a| *a != "--wait" && *a != "--dry-run" && *a != "-n").collect();
+
     // return if no files
-    if files.len() == 0 { return }
+    if files.is_empty() {
+        eprintln!("usage: nep-add [--wait] [--dry-run] <path>...");
+        return
+    }
 
-    // ensure
-    let mut manifest = manifest::Manifest::new(proj::MANIFEST).unwrap();
+    let cwd = match std::env::current_dir() {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
 
-    println!("manifest {} object loaded.", manifest.anno_map.len());
+    // new project in cwd, if not in any
+    let project = match Project::find_from(&cwd) {
+        Some(p) => p,
+        None if dry_run => {
+            eprintln!("not in a project, {} will be created in current directory",
+                      proj::MANIFEST);
+            Project { root: cwd }
+        },
+        None => match fs::create_dir(cwd.join(proj::MANIFEST)) {
+            Ok(_) => {
+                println!("create {} in current directory", proj::MANIFEST);
+                Project { root: cwd }
+            },
+            Err(e) => {
+                eprintln!("fail to create {}, {}", proj::MANIFEST, e);
+                std::process::exit(1);
+            },
+        },
+    };
+
+    // none for dry run without project
+    let mut manifest = if project.root.join(proj::MANIFEST).is_dir() {
+        match manifest::Manifest::open(&project.mdir(), wait) {
+            Ok(m) => Some(m),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            },
+        }
+    }
+    else {
+        None
+    };
+
+    let loaded = manifest.as_ref().map_or(0, |m| m.anno_map.len());
+    println!("manifest {} object loaded.", loaded);
 
     for f in files {
-        print!("adding {} ... ", f);
-        io::stdout().flush().ok();
+        let rpaths = match expand(&project, manifest.as_ref(), f) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("adding {} ... error, {}", f, e);
+                continue
+            },
+        };
 
-        match manifest.add(&f.to_string()) {
-            Ok(_) => println!("ok"),
-            Err(e) => println!("error, {}", e)
+        for r in rpaths {
+            let m = match manifest.as_mut() {
+                Some(m) if !dry_run => m,
+                _ => {
+                    println!("add {}", r);
+                    continue
+                },
+            };
+
+            print!("adding {} ... ", r);
+            io::stdout().flush().ok();
+
+            match m.add(&r) {
+                Ok(_) => println!("ok"),
+                Err(e) => println!("error, {}", e)
+            }
         }
     }
 
-    println!("manifest {} object total", manifest.anno_map.len());
+    if let (Some(m), false) = (manifest.as_ref(), dry_run) {
+        println!("manifest {} object total", m.anno_map.len());
+    }
 }
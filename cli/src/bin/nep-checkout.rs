@@ -0,0 +1,133 @@
+// nephrite-checkout
+use dotenv::dotenv;
+
+use nephrite4_common::proj;
+use nephrite4_common::conf;
+use nephrite4_common::store;
+use nephrite4_common::util;
+use nephrite4_common::error::*;
+
+use proj::anno::{Anno, St};
+use proj::lock::Lock;
+use proj::Project;
+use store::Store;
+use conf::Conf;
+
+use std::path::Path;
+
+use log::debug;
+
+// target is path in manifest, or anno id/prefix
+fn checkout(store: &Store, project: &Project, target: &str, force: bool)
+            -> Result<()> {
+    let mdir = project.mdir();
+    let target_rpath = project.rpath(target).ok()
+        .filter(|r| Path::new(&mdir).join(r.to_string() + ".meta").is_file());
+
+    let (aid, rpath) = if let Some(r) = target_rpath {
+        let anno = Anno::load(&mdir, &r)?;
+
+        match anno.pid.first() {
+            Some(pid) => (*pid, r),
+            None => return err("not committed yet"),
+        }
+    }
+    else {
+        let aid = store.resolve_anno(target)?;
+        let anno = store.read_commit_anno(&aid, true)?;
+
+        match anno.get_name() {
+            Some(name) => (aid, name),
+            None => return err("anno without name"),
+        }
+    };
+
+    debug!("checkout: {} -> {}", util::to_zbase32(&aid), rpath);
+
+    let committed = store.read_commit_anno(&aid, true)?;
+
+    let tracked = Path::new(&mdir).join(rpath.clone() + ".meta").is_file();
+
+    // do not overwrite local change
+    if project.path(&rpath).is_file() && !force {
+        if !tracked {
+            return err("untracked file exists, use --force to overwrite")
+        }
+
+        let an = Anno::new(&mdir, &rpath, false)?;
+        let st = an.status()?;
+
+        if st == St::Ready && an.pid.first() == Some(&aid) {
+            println!("{} up to date", rpath);
+            return Ok(())
+        }
+
+        if st != St::Ready {
+            return err("local change exists, use --force to overwrite")
+        }
+    }
+
+    let size = store.checkout(&committed, &project.path(&rpath))?;
+
+    let mut anno = if tracked { Anno::load(&mdir, &rpath)? }
+                   else { Anno::new(&mdir, &rpath, false)? };
+
+    anno.reset(&aid, &committed)?;
+
+    println!("{} {} -> {} ({} bytes)",
+             &util::to_zbase32(&aid)[..8],
+             &util::to_zbase32(&committed.fid)[..8],
+             rpath, size);
+
+    Ok(())
+}
+
+fn main() {
+    dotenv().ok();
+
+    env_logger::init();
+
+    let args = std::env::args_os().skip(1).
+        map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+
+    let force = args.iter().any(|a| a == "--force" || a == "-f");
+    let wait = args.iter().any(|a| a == "--wait");
+    let targets: Vec<&String> = args.iter()
+        .filter(|a| *a != "--force" && *a != "-f" && *a != "--wait")
+        .collect();
+
+    if targets.is_empty() {
+        eprintln!("usage: nep-checkout [--force] [--wait] <path|id>...");
+        return
+    }
+
+    let project = match Project::find() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let _lock = match Lock::acquire(&project.mdir(), wait) {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let store = match Conf::read().and_then(|c| Store::new(&c)) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    for t in targets {
+        if let Err(e) = checkout(&store, &project, t, force) {
+            println!("{}: error, {}", t, e);
+        }
+    }
+}
@@ -2,6 +2,7 @@
 use nephrite4_common::proj;
 use nephrite4_common::conf;
 use nephrite4_common::store;
+use nephrite4_common::output;
 
 use proj::manifest;
 use store::*;
@@ -9,19 +10,78 @@ use conf::Conf;
 
 use log::debug;
 
+use serde_json::json;
+
 fn main() {
     env_logger::init();
 
-    let conf = Conf::read();
     // store init
-    let mut store = Store::new(&conf).unwrap();
+    let mut store = match Conf::read().and_then(|c| Store::new(&c)) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    let wait = args.iter().any(|a| a == "--wait");
+    let rollback = args.iter().any(|a| a == "--rollback");
+    let json = output::enabled(&args);
+
+    store.quiet = json;
 
-    let mut manifest = manifest::Manifest::new(proj::MANIFEST).unwrap();
+    let mut manifest = match manifest::Manifest::find(wait) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
 
-    println!("manifest {} object loaded.", manifest.anno_map.len());
+    if !json {
+        println!("manifest {} object loaded.", manifest.anno_map.len());
+    }
 
-    // do commit
-    let commit = store.commit(&mut manifest).unwrap();
+    // undo interrupted commit
+    if rollback {
+        let n = match store.rollback(&mut manifest) {
+            Ok(n) => n,
+            Err(e) => {
+                eprintln!("rollback fail, {}", e);
+                std::process::exit(1);
+            },
+        };
+        if json {
+            output::emit(json!({"type": "rollback", "annos": n}));
+        }
+        else {
+            println!("{} anno commit rolled back", n);
+        }
+        return
+    }
+
+    // do commit, resume interrupted one if any
+    let commit = match store.commit(&mut manifest) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
 
     debug!("commit -> {:?}", commit);
+
+    if json {
+        for (path, aid, resume) in commit.annos.iter() {
+            output::emit(json!({"type": "anno", "path": path,
+                                "aid": output::id(aid), "resume": resume}));
+        }
+
+        output::emit(json!({"type": "commit",
+                            "cset": commit.oid.as_ref().map(output::id),
+                            "tree": commit.tree.as_ref().map(output::id),
+                            "annos": commit.annos.len()}));
+    }
 }
@@ -0,0 +1,209 @@
+// nephrite-edit
+use dotenv::dotenv;
+
+use nephrite4_common::proj;
+use nephrite4_common::util;
+use nephrite4_common::error::*;
+
+use proj::anno::{self, Anno};
+use proj::manifest::Manifest;
+use proj::Project;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+// line written by nep-edit, removed when read back
+const NOTE: &str = "#>";
+
+// private dir for temp file, removed when drop
+struct TmpDir(PathBuf);
+
+impl TmpDir {
+    fn new() -> Result<TmpDir> {
+        for i in 0.. {
+            let path = env::temp_dir().join(format!("nep-edit-{}-{}", process::id(), i));
+
+            match DirBuilder::new().mode(0o700).create(&path) {
+                Ok(()) => return Ok(TmpDir(path)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        unreachable!()
+    }
+}
+
+impl Drop for TmpDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+fn editor() -> String {
+    env::var("VISUAL").or_else(|_| env::var("EDITOR"))
+        .ok().filter(|e| !e.is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+// editor may have argument, like git
+fn run_editor(path: &Path) -> Result<()> {
+    let ed = editor();
+    let st = Command::new("sh").arg("-c").arg(format!("{} \"$@\"", ed))
+        .arg(&ed).arg(path).status()?;
+
+    if !st.success() {
+        return err(&format!("editor '{}' fail, {}", ed, st))
+    }
+
+    Ok(())
+}
+
+fn show<T: serde::Serialize>(v: &T) -> String {
+    serde_json::to_string(v).unwrap_or_else(|_| "?".to_string())
+}
+
+fn diff<T: serde::Serialize + PartialEq>(old: &BTreeMap<String, T>,
+                                        new: &BTreeMap<String, T>) -> Vec<String> {
+    let mut res = vec![];
+
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+
+    for k in keys {
+        match (old.get(k), new.get(k)) {
+            (Some(a), Some(b)) if a == b => (),
+            (a, b) => {
+                if let Some(a) = a { res.push(format!("- {}: {}", k, show(a))); }
+                if let Some(b) = b { res.push(format!("+ {}: {}", k, show(b))); }
+            },
+        }
+    }
+
+    res
+}
+
+fn edit(manifest: &mut Manifest, project: &Project, arg: &str) -> Result<()> {
+    let rpath = project.rpath(arg)?;
+    let schema = &manifest.schema;
+
+    let anno = match manifest.anno_map.get_mut(&rpath) {
+        Some(a) => a,
+        None => return err(&format!("{} not in manifest", rpath)),
+    };
+
+    let (fixed, user): (BTreeMap<_, _>, BTreeMap<_, _>) = anno.data.clone().into_iter()
+        .partition(|(k, _)| schema.is_builtin(k) || k == anno::REMOVED);
+
+    let mut head = vec![format!("{} {}, quit without saving to abort", NOTE, rpath),
+                        format!("{} read only:", NOTE)];
+    head.extend(fixed.iter().map(|(k, v)| format!("{}   {}: {}", NOTE, k, show(v))));
+    let pid = anno.pid.iter().map(|p| util::to_zbase32(p)).collect::<Vec<_>>();
+    head.push(format!("{}   pid: {}", NOTE,
+                      if pid.is_empty() { "-".to_string() } else { pid.join(", ") }));
+
+    let mut body = if user.is_empty() { String::new() }
+                   else { serde_yaml::to_string(&user)? };
+    let mut error: Option<String> = None;
+
+    let name = Path::new(&rpath).file_name().unwrap().to_string_lossy().to_string();
+    let dir = TmpDir::new()?;
+    let tmp = dir.0.join(format!("{}.yaml", name));
+
+    // until valid, or abort
+    let data = loop {
+        let mut content = head.join("\n") + "\n";
+        if let Some(e) = error.take() {
+            for l in e.lines() { content += &format!("{} error: {}\n", NOTE, l); }
+        }
+        content += &body;
+
+        fs::write(&tmp, &content)?;
+        let res = run_editor(&tmp).and_then(|_| Ok(fs::read_to_string(&tmp)?));
+        fs::remove_file(&tmp).ok();
+
+        // buffer untouched, empty body is valid and clear all user keys
+        let read = res?;
+        if read == content {
+            println!("abort, {} not changed", rpath);
+            return Ok(())
+        }
+
+        body = read.lines().filter(|l| !l.starts_with(NOTE))
+            .map(|l| l.to_string() + "\n").collect();
+
+        let checked = Anno::parse_data(&body).and_then(|mut d| {
+            if let Some(k) = d.keys().find(|k| schema.is_builtin(k) || *k == anno::REMOVED) {
+                return err(&format!("{} is read only", k))
+            }
+
+            d.extend(fixed.clone());
+            schema.validate(&d)?;
+            Ok(d)
+        });
+
+        match checked {
+            Ok(d) => break d,
+            Err(Error::Simple(m)) => error = Some(m),
+            Err(e) => error = Some(e.to_string()),
+        }
+    };
+
+    let changes = diff(&anno.data, &data);
+
+    if changes.is_empty() {
+        println!("{} not changed", rpath);
+        return Ok(())
+    }
+
+    for l in changes.iter() { println!("{}", l); }
+
+    anno.data = data;
+    anno.save()?;
+
+    println!("U {} -- {}", changes.len(), rpath);
+
+    Ok(())
+}
+
+fn main() {
+    dotenv().ok();
+
+    env_logger::init();
+
+    let args = std::env::args_os().skip(1).
+        map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+
+    let wait = args.iter().any(|a| a == "--wait");
+    let paths: Vec<&String> = args.iter().filter(|a| *a != "--wait").collect();
+
+    if paths.len() != 1 {
+        eprintln!("usage: nep-edit [--wait] <path>");
+        std::process::exit(2);
+    }
+
+    let project = match Project::find() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let mut manifest = match Manifest::open(&project.mdir(), wait) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    if let Err(e) = edit(&mut manifest, &project, paths[0]) {
+        eprintln!("{}: error, {}", paths[0], e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,79 @@
+// nephrite-fsck
+//
+// each problem first, then summary; NDJSON schema with --json in output.rs
+use dotenv::dotenv;
+
+use nephrite4_common::conf;
+use nephrite4_common::fsck;
+use nephrite4_common::output;
+use nephrite4_common::store;
+use nephrite4_common::util;
+
+use store::Store;
+use conf::Conf;
+
+use std::process;
+
+fn main() {
+    dotenv().ok();
+
+    env_logger::init();
+
+    let args = std::env::args_os().skip(1).
+        map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+
+    let no_dangling = args.iter().any(|a| a == "--no-dangling");
+    let json = output::enabled(&args);
+
+    if args.iter().any(|a| a != "--no-dangling" && a != output::JSON_FLAG) {
+        eprintln!("usage: nep-fsck [--no-dangling] [--json]");
+        process::exit(2);
+    }
+
+    let store = match Conf::read().and_then(|c| Store::new(&c)) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let report = match fsck::check(&store, !no_dangling) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("fsck fail, {}", e);
+            process::exit(2);
+        },
+    };
+
+    if json {
+        for p in report.problems.iter() {
+            output::emit(p.to_json());
+        }
+
+        output::emit(report.summary_json());
+    }
+    else {
+        for p in report.problems.iter() {
+            let cset = p.cset.map_or("".to_string(),
+                                     |c| format!(" in changeset {}", &util::to_zbase32(&c)[..8]));
+            println!("{} {} {}{}{}{}", p.kind.str(), p.what, util::to_zbase32(&p.oid), cset,
+                     if p.msg.is_empty() { "" } else { ", " }, p.msg);
+        }
+
+        println!("{} changeset, {} anno, {} file checked",
+                 report.csets, report.annos, report.files);
+        println!("{} dangling, {} corrupt, {} unreadable",
+                 report.count(fsck::Kind::Dangling), report.count(fsck::Kind::Corrupt),
+                 report.count(fsck::Kind::Unreadable));
+
+        if report.dangling_skipped {
+            println!("dangling check skipped, some changeset can not be walked");
+        }
+    }
+
+    // dangling object is harmless
+    if report.problems.iter().any(|p| p.kind != fsck::Kind::Dangling) {
+        process::exit(1);
+    }
+}
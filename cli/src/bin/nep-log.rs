@@ -0,0 +1,93 @@
+// nephrite-log
+use dotenv::dotenv;
+
+use nephrite4_common::proj;
+use nephrite4_common::conf;
+use nephrite4_common::store;
+use nephrite4_common::util;
+use nephrite4_common::error::*;
+
+use proj::anno::Anno;
+use proj::Project;
+use store::Store;
+use conf::Conf;
+
+use chrono::prelude::*;
+
+use std::path::Path;
+
+// target is path in manifest, or anno id/prefix
+fn log(store: &Store, target: &str) -> Result<()> {
+    // path only make sense inside project
+    let tracked = Project::find().ok().and_then(|p| {
+        let mdir = p.mdir();
+        p.rpath(target).ok()
+            .filter(|r| Path::new(&mdir).join(r.to_string() + ".meta").is_file())
+            .map(|r| (mdir, r))
+    });
+
+    let aid = if let Some((mdir, rpath)) = tracked {
+        match Anno::load(&mdir, &rpath)?.pid.first() {
+            Some(pid) => *pid,
+            None => return err("not committed yet"),
+        }
+    }
+    else {
+        store.resolve_anno(target)?
+    };
+
+    let root = store.anno_root(&aid)?;
+    let tip = store.anno_tip(&aid)?;
+
+    println!("root {}", util::to_zbase32(&root));
+
+    for (id, anno) in store.anno_log(&tip)? {
+        let date = anno.get_mtime()
+            .and_then(|t| Local.timestamp_millis_opt(t as i64).single())
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default();
+
+        println!();
+        println!("anno {}", util::to_zbase32(&id));
+        for pid in anno.pid.iter() {
+            println!("parent {}", util::to_zbase32(pid));
+        }
+        println!("file {}", util::to_zbase32(&anno.fid));
+        println!("date {}", date);
+        println!();
+
+        for ln in anno.gen_yaml()?.lines().skip(1) {
+            println!("    {}", ln);
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    dotenv().ok();
+
+    env_logger::init();
+
+    let args = std::env::args_os().skip(1).
+        map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+
+    if args.is_empty() {
+        eprintln!("usage: nep-log <path|id>...");
+        return
+    }
+
+    let store = match Conf::read().and_then(|c| Store::new(&c)) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    for t in args.iter() {
+        if let Err(e) = log(&store, t) {
+            println!("{}: error, {}", t, e);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// nephrite-merge
+use dotenv::dotenv;
+
+use nephrite4_common::proj;
+use nephrite4_common::conf;
+use nephrite4_common::store;
+use nephrite4_common::util;
+use nephrite4_common::error::*;
+
+use proj::anno::{Anno, St};
+use proj::manifest::Manifest;
+use proj::Project;
+use proj::merge::{self, Side};
+use store::Store;
+use conf::Conf;
+
+use util::Id;
+
+use std::collections::BTreeMap;
+
+use log::debug;
+
+// merge tip of same anno from other host into local sidecar
+fn merge(store: &Store, heads: &BTreeMap<Id, Vec<Id>>,
+         name: &str, anno: &mut Anno) -> Result<()> {
+    let local = match anno.pid.first() {
+        Some(x) => *x,
+        None => return Ok(()),
+    };
+
+    let root = store.anno_root(&local)?;
+    let ancestors = store.anno_ancestors(&local)?;
+
+    let others: Vec<&Id> = heads.get(&root).into_iter().flatten()
+        .filter(|h| !ancestors.contains(*h))
+        .collect();
+
+    let other = match others.first() {
+        Some(x) => **x,
+        None => return Ok(()),
+    };
+
+    if anno.status()? != St::Ready {
+        return err("local change exists, commit first")
+    }
+
+    let theirs = store.read_commit_anno(&other, true)?;
+    let base = store.anno_merge_base(&local, &other)?;
+
+    debug!("merge: {} local {} other {} base {:?}", name,
+           util::to_zbase32(&local), util::to_zbase32(&other),
+           base.map(|b| util::to_zbase32(&b)));
+
+    if base == Some(local) {
+        if theirs.fid != anno.fid {
+            store.checkout(&theirs, &anno.get_file_path())?;
+        }
+
+        anno.reset(&other, &theirs)?;
+
+        println!("{}: fast-forward to {}", name, &util::to_zbase32(&other)[..8]);
+        return Ok(())
+    }
+
+    let ours = store.read_commit_anno(&local, true)?;
+    let base = match base {
+        Some(b) => Some(store.read_commit_anno(&b, true)?),
+        None => None,
+    };
+
+    let base_fid = base.as_ref().map(|b| b.fid);
+    let empty = BTreeMap::new();
+    let base_data = base.as_ref().map_or(&empty, |b| &b.data);
+
+    let file = if ours.fid == theirs.fid { None }
+               else if base_fid == Some(ours.fid) { Some(Side::Theirs) }
+               else {
+                   if base_fid != Some(theirs.fid) {
+                       println!("{}: content diverged, keep local, other is {}",
+                                name, &util::to_zbase32(&theirs.fid)[..8]);
+                   }
+                   Some(Side::Ours)
+               };
+
+    let merged = merge::merge(base_data, &ours.data, &theirs.data, file);
+
+    if file == Some(Side::Theirs) {
+        store.checkout(&theirs, &anno.get_file_path())?;
+        anno.fid = theirs.fid;
+    }
+
+    anno.pid = vec![local, other];
+    anno.data = merged.data.clone();
+    // force a merge commit, even if data not change
+    anno.anno_hash = [0; 32];
+
+    if merged.conflicts.is_empty() {
+        anno.save()?;
+        println!("{}: merged {}", name, &util::to_zbase32(&other)[..8]);
+    }
+    else {
+        let yaml = merge::gen_conflict_yaml(
+            &merged,
+            &format!("local {}", &util::to_zbase32(&local)[..8]),
+            &format!("other {}", &util::to_zbase32(&other)[..8]))?;
+
+        anno.save_conflict(&yaml)?;
+
+        let keys: Vec<&str> = merged.conflicts.iter()
+            .map(|c| c.key.as_str()).collect();
+        println!("{}: conflict in {}, edit then nep-resolve",
+                 name, keys.join(", "));
+    }
+
+    if others.len() > 1 {
+        println!("{}: {} more version to merge after commit",
+                 name, others.len() - 1);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    dotenv().ok();
+
+    env_logger::init();
+
+    let args = std::env::args_os().skip(1).
+        map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+
+    let wait = args.iter().any(|a| a == "--wait");
+    let args: Vec<String> = args.into_iter().filter(|a| a != "--wait").collect();
+
+    let store = match Conf::read().and_then(|c| Store::new(&c)) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let project = match Project::find() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let names: Vec<String> = match args.iter().map(|a| project.rpath(a)).collect() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let mut manifest = match Manifest::open(&project.mdir(), wait) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let heads = match store.anno_heads() {
+        Ok(h) => h,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    for (name, anno) in manifest.anno_map.iter_mut() {
+        if !names.is_empty() && !names.contains(name) { continue }
+
+        if let Err(e) = merge(&store, &heads, name, anno) {
+            println!("{}: error, {}", name, e);
+        }
+    }
+}
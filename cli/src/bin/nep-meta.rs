@@ -3,65 +3,109 @@
 use dotenv::dotenv;
 
 use nephrite4_common::proj;
+use nephrite4_common::output;
 use proj::manifest;
+use proj::op::{self, Op};
+use proj::Project;
+
+use std::path::Path;
 
 use log::debug;
 
+use serde_json::json;
+
 fn main() {
     dotenv().ok();
 
     env_logger::init();
 
-    // ensure
-    let mut manifest = manifest::Manifest::new(proj::MANIFEST).unwrap();
-
-    println!("manifest {} object loaded.", manifest.anno_map.len());
-
     // get id
     let args = std::env::args_os().skip(1).
         map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
 
-    let mut ops: Vec<String> = vec![];
-    let mut files: Vec<String> = vec![];
+    let wait = args.iter().any(|a| a == "--wait");
+    let json = output::enabled(&args);
+    let args: Vec<String> = args.into_iter()
+        .filter(|a| a != "--wait" && a != output::JSON_FLAG).collect();
+
+    let project = match Project::find() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let mut manifest = match manifest::Manifest::open(&project.mdir(), wait) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    if !json {
+        println!("manifest {} object loaded.", manifest.anno_map.len());
+    }
 
-    let mut is_op = true;
-    let mut allow_new = false;
+    // existing file is a path, even with op char, see op.rs
+    let (ops, files, allow_new) = op::split_args(args, |a| Path::new(a).exists());
 
-    for a in args.into_iter() {
-        if is_op {
-            if a == "--new" {
-                allow_new = true;
-                continue;
-            }
+    debug!("ops -- {:?}, files -- {:?}", ops, files);
 
-            if a == "--" {
-                is_op = false;
-                continue;
-            }
+    // check all before change any file
+    let ops = match ops.iter().map(|a| Op::parse(a)).collect::<Result<Vec<_>, _>>() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        },
+    };
 
-            if a.contains(|c| c == '+' || c == '-' || c == '=') {
-                ops.push(a);
-            }
-            else {
-                files.push(a);
-                is_op = false;
+    for f in files.into_iter() {
+        let rpath = match project.rpath(&f) {
+            Ok(r) => r,
+            Err(e) if json => {
+                output::emit(json!({"type": "error", "path": f, "msg": e.to_string()}));
+                continue;
+            },
+            Err(e) => {
+                println!("E {} -- {}", e, f);
+                continue;
+            },
+        };
+
+        let anno_opt = manifest.anno_map.get_mut(&rpath);
+        if anno_opt.is_none() {
+            if json {
+                output::emit(json!({"type": "error", "path": rpath,
+                                    "msg": "not in manifest"}));
             }
+            continue;
         }
-        else {
-            files.push(a)
-        }
-    }
-
-    debug!("ops -- {:?}, files -- {:?}", ops, files);
-
-    for f in files.into_iter() {
-        let anno_opt = manifest.anno_map.get_mut(&f);
-        if anno_opt.is_none() { continue; }
         let anno = anno_opt.unwrap();
 
-        let n = anno.proc_op(&ops, allow_new);
+        let n = match anno.apply(&manifest.schema, &ops, allow_new) {
+            Ok(n) => n,
+            Err(e) if json => {
+                output::emit(json!({"type": "error", "path": rpath, "msg": e.to_string()}));
+                continue;
+            },
+            Err(e) => {
+                println!("E {} -- {}", e, f);
+                continue;
+            },
+        };
         anno.save().unwrap();
 
+        if json {
+            output::emit(json!({"type": "meta", "path": rpath, "changed": n,
+                                "fid": output::id(&anno.fid),
+                                "pid": output::ids(&anno.pid),
+                                "attr": output::attr(&anno.data)}));
+            continue;
+        }
+
         println!("U {} -- {}", n, f);
     }
 }
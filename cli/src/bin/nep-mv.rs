@@ -0,0 +1,88 @@
+// nephrite-mv
+use dotenv::dotenv;
+
+use nephrite4_common::proj;
+use nephrite4_common::conf;
+use nephrite4_common::store;
+use nephrite4_common::util;
+use nephrite4_common::error::*;
+
+use proj::manifest::Manifest;
+use proj::Project;
+use store::Store;
+use conf::Conf;
+
+use std::path::Path;
+
+// move then commit, history continue from old pid
+fn mv(store: &mut Store, project: &Project, manifest: &mut Manifest,
+      old: &str, new: &str) -> Result<()> {
+    let old = project.rpath(old)?;
+    let mut new = project.rpath(new)?;
+
+    // into directory, like mv
+    if project.path(&new).is_dir() {
+        let name = Path::new(&old).file_name().unwrap().to_string_lossy().to_string();
+        new = Path::new(&new).join(name).to_string_lossy().to_string();
+    }
+
+    manifest.mv(&old, &new)?;
+
+    let res = store.commit_only(manifest, Some(&[new.clone()]))?;
+
+    let aid = manifest.anno_map[&new].pid.first()
+        .map_or("-".to_string(), |p| util::to_zbase32(p)[..8].to_string());
+
+    println!("{} -> {} {}{}", old, new, aid,
+             if res.oid.is_none() { " (not committed)" } else { "" });
+
+    Ok(())
+}
+
+fn main() {
+    dotenv().ok();
+
+    env_logger::init();
+
+    let args = std::env::args_os().skip(1).
+        map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+
+    let wait = args.iter().any(|a| a == "--wait");
+    let paths: Vec<&String> = args.iter().filter(|a| *a != "--wait").collect();
+
+    if paths.len() != 2 {
+        eprintln!("usage: nep-mv [--wait] <old> <new>");
+        std::process::exit(2);
+    }
+
+    let mut store = match Conf::read().and_then(|c| Store::new(&c)) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    store.quiet = true;
+
+    let project = match Project::find() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let mut manifest = match Manifest::open(&project.mdir(), wait) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    if let Err(e) = mv(&mut store, &project, &mut manifest, paths[0], paths[1]) {
+        eprintln!("{}: error, {}", paths[0], e);
+        std::process::exit(1);
+    }
+}
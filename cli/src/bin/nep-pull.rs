@@ -0,0 +1,71 @@
+// nephrite-pull
+use dotenv::dotenv;
+
+use nephrite4_common::conf;
+use nephrite4_common::git;
+use nephrite4_common::store;
+use nephrite4_common::sync;
+use nephrite4_common::util;
+use nephrite4_common::error::*;
+
+use store::Store;
+use conf::Conf;
+
+use std::path::Path;
+
+// changesets of repo as changesets of host, with relayed ones
+fn pull(local: &Store, repo: &str, host: &str) -> Result<()> {
+    if !Path::new(repo).join("objects").is_dir() {
+        return err(&format!("{} is not a store", repo))
+    }
+
+    let remote = Store::open(repo)?;
+
+    for (h, res) in sync::sync_all(&remote, local, host)? {
+        let (old, tip) = match sync::report(&h, res) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        // edited here too
+        for (_, tree) in local.walk(&tip, old.as_ref())? {
+            for te in tree.iter().filter(|te| te.mode == git::Type::Commit) {
+                if let Some(l) = local.anno_diverged(&te.oid)? {
+                    println!("  {} diverged from local {}, run nep-merge",
+                             &util::to_zbase32(&te.oid)[..8],
+                             &util::to_zbase32(&l)[..8]);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    dotenv().ok();
+
+    env_logger::init();
+
+    let args = std::env::args_os().skip(1).
+        map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+
+    if args.len() != 2 || args[1] == Store::LOCALHOST {
+        eprintln!("usage: nep-pull <repo> <host>");
+        eprintln!("  host: name of repo here");
+        std::process::exit(2);
+    }
+
+    let store = match Conf::read().and_then(|c| Store::new(&c)) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    if let Err(e) = pull(&store, &args[0], &args[1]) {
+        println!("pull fail, {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,55 @@
+// nephrite-push
+use dotenv::dotenv;
+
+use nephrite4_common::conf;
+use nephrite4_common::store;
+use nephrite4_common::sync;
+use nephrite4_common::error::*;
+
+use store::Store;
+use conf::Conf;
+
+use std::path::Path;
+
+// local changesets to repo as changesets of host, with relayed ones
+fn push(local: &Store, repo: &str, host: &str) -> Result<()> {
+    if !Path::new(repo).join("objects").is_dir() {
+        return err(&format!("{} is not a store", repo))
+    }
+
+    let remote = Store::open(repo)?;
+
+    for (h, res) in sync::sync_all(local, &remote, host)? {
+        sync::report(&h, res);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    dotenv().ok();
+
+    env_logger::init();
+
+    let args = std::env::args_os().skip(1).
+        map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+
+    if args.len() != 2 || args[1] == Store::LOCALHOST {
+        eprintln!("usage: nep-push <repo> <host>");
+        eprintln!("  host: name of this host in repo");
+        std::process::exit(2);
+    }
+
+    let store = match Conf::read().and_then(|c| Store::new(&c)) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    if let Err(e) = push(&store, &args[0], &args[1]) {
+        println!("push fail, {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,83 @@
+// nephrite-resolve
+use dotenv::dotenv;
+
+use nephrite4_common::proj;
+use nephrite4_common::util;
+use nephrite4_common::error::*;
+
+use proj::anno::Anno;
+use proj::lock::Lock;
+use proj::Project;
+use proj::merge::{self, Side};
+
+use std::fs;
+use std::path::Path;
+
+// clear conflict markers left by nep-merge
+fn resolve(project: &Project, path: &str, side: Option<Side>) -> Result<()> {
+    let mdir = project.mdir();
+    let rpath = project.rpath(path)?;
+    let yaml = Path::new(&mdir).join(rpath.clone() + ".yaml");
+    let content = fs::read_to_string(&yaml)?;
+
+    if merge::has_conflict(&content) {
+        match side {
+            Some(s) => util::write_atomic(&yaml, merge::resolve(&content, s)?.as_bytes())?,
+            None => return err("conflict marker remain, edit or use --ours/--theirs"),
+        }
+    }
+
+    // check & normalize yaml
+    let mut anno = Anno::load(&mdir, &rpath)?;
+    anno.save()?;
+
+    println!("{}: resolved", rpath);
+
+    Ok(())
+}
+
+fn main() {
+    dotenv().ok();
+
+    env_logger::init();
+
+    let args = std::env::args_os().skip(1).
+        map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+
+    let side = if args.iter().any(|a| a == "--ours") { Some(Side::Ours) }
+               else if args.iter().any(|a| a == "--theirs") { Some(Side::Theirs) }
+               else { None };
+
+    let wait = args.iter().any(|a| a == "--wait");
+
+    let paths: Vec<&String> = args.iter()
+        .filter(|a| *a != "--ours" && *a != "--theirs" && *a != "--wait")
+        .collect();
+
+    if paths.is_empty() {
+        eprintln!("usage: nep-resolve [--ours|--theirs] [--wait] <path>...");
+        return
+    }
+
+    let project = match Project::find() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let _lock = match Lock::acquire(&project.mdir(), wait) {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    for p in paths {
+        if let Err(e) = resolve(&project, p, side) {
+            println!("{}: error, {}", p, e);
+        }
+    }
+}
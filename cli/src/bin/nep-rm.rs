@@ -0,0 +1,110 @@
+// nephrite-rm
+use dotenv::dotenv;
+
+use nephrite4_common::proj;
+use nephrite4_common::conf;
+use nephrite4_common::store;
+use nephrite4_common::util;
+use nephrite4_common::error::*;
+
+use proj::anno::St;
+use proj::manifest::Manifest;
+use proj::Project;
+use store::Store;
+use conf::Conf;
+
+// tombstone committed history, then stop tracking, file is kept
+fn rm(store: &mut Store, project: &Project, manifest: &mut Manifest,
+      paths: &[&String], force: bool) -> Result<()> {
+    let mut rpaths = vec![];
+    let mut tips = vec![];
+
+    for p in paths {
+        let rpath = project.rpath(p)?;
+
+        let anno = match manifest.anno_map.get(&rpath) {
+            Some(a) => {
+                if !force && a.status()? != St::Ready {
+                    return err(&format!("{} has uncommitted change, \
+                                         commit first or use --force", rpath))
+                }
+                a
+            },
+            None => match manifest.missing.get(&rpath) {
+                Some(a) => a,
+                None => return err(&format!("{} not in manifest", rpath)),
+            },
+        };
+
+        // never committed, nothing to tombstone
+        if let Some(pid) = anno.pid.first() {
+            tips.push((rpath.clone(), *pid));
+        }
+
+        rpaths.push(rpath);
+    }
+
+    // sidecars kept until tombstones in changeset, rerun if fail after
+    let res = store.commit_removed(&manifest.mdir, &tips)?;
+
+    for rpath in rpaths.iter() {
+        manifest.forget(rpath)?;
+
+        let aid = res.annos.iter().find(|(n, _, _)| n == rpath)
+            .map_or("-".to_string(), |(_, a, _)| util::to_zbase32(a)[..8].to_string());
+
+        println!("rm {} {}", rpath, aid);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    dotenv().ok();
+
+    env_logger::init();
+
+    let args = std::env::args_os().skip(1).
+        map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+
+    let wait = args.iter().any(|a| a == "--wait");
+    let force = args.iter().any(|a| a == "--force");
+    let paths: Vec<&String> = args.iter()
+        .filter(|a| *a != "--wait" && *a != "--force").collect();
+
+    if paths.is_empty() {
+        eprintln!("usage: nep-rm [--wait] [--force] <path>...");
+        std::process::exit(2);
+    }
+
+    let mut store = match Conf::read().and_then(|c| Store::new(&c)) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    store.quiet = true;
+
+    let project = match Project::find() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let mut manifest = match Manifest::open(&project.mdir(), wait) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    if let Err(e) = rm(&mut store, &project, &mut manifest, &paths, force) {
+        eprintln!("error, {}", e);
+        std::process::exit(1);
+    }
+}
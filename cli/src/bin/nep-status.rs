@@ -3,8 +3,12 @@ use dotenv::dotenv;
 
 use nephrite4_common::proj;
 use nephrite4_common::util;
+use nephrite4_common::output;
 
 use proj::manifest;
+use proj::journal::Journal;
+
+use serde_json::json;
 
 //use std::io;
 
@@ -13,10 +17,76 @@ fn main() {
 
     env_logger::init();
 
-    let manifest = manifest::Manifest::new(proj::MANIFEST).unwrap();
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    let wait = args.iter().any(|a| a == "--wait");
+    let json = output::enabled(&args);
+
+    let manifest = match manifest::Manifest::find(wait) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let moves = match manifest.moves() {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("move detection fail, {}", e);
+            vec![]
+        },
+    };
+
+    if json {
+        let journal = Journal::new(&manifest.mdir);
+        if journal.exists() {
+            output::emit(json!({"type": "journal",
+                                "path": journal.path().to_string_lossy()}));
+        }
+
+        for (name, anno) in manifest.anno_map.iter() {
+            let (st, error) = match anno.status() {
+                Ok(st) => (output::status(st), None),
+                Err(e) => ("error", Some(e.to_string())),
+            };
+
+            output::emit(json!({"type": "status", "path": name, "status": st,
+                                "fid": output::id(&anno.fid),
+                                "pid": output::ids(&anno.pid),
+                                "error": error}));
+        }
+
+        for (name, anno) in manifest.missing.iter() {
+            output::emit(json!({"type": "status", "path": name, "status": "missing",
+                                "fid": output::id(&anno.fid),
+                                "pid": output::ids(&anno.pid),
+                                "error": null}));
+        }
+
+        for (name, e) in manifest.failed.iter() {
+            output::emit(json!({"type": "status", "path": name, "status": "error",
+                                "fid": null, "pid": [], "error": e}));
+        }
+
+        for (from, to) in moves.iter() {
+            output::emit(json!({"type": "move", "from": from, "to": to}));
+        }
+
+        if !manifest.failed.is_empty() {
+            std::process::exit(1);
+        }
+
+        return
+    }
 
     println!("manifest {} object loaded.", manifest.anno_map.len());
 
+    if Journal::new(&manifest.mdir).exists() {
+        println!("last commit interrupted, run nep-commit to resume, \
+                  or nep-commit --rollback to undo");
+    }
+
     for (name, anno) in manifest.anno_map.iter() {
         let st_sym = match anno.status() {
             Ok(st) => proj::anno::st2chr(st),
@@ -36,4 +106,29 @@ fn main() {
                  st_sym,
                  name);
     }
+
+    // moved if content found at new path, else deleted
+    for (name, anno) in manifest.missing.iter() {
+        let pid0 = anno.pid.first().map_or("        ".to_string(), |p| util::to_zbase32(p));
+        let fid = util::to_zbase32(&anno.fid);
+
+        match moves.iter().find(|(from, _)| from == name) {
+            Some((_, to)) => println!("{} {} -> R : {:?} -> {:?}",
+                                      &pid0[..8], &fid[..8], name, to),
+            None => println!("{} {} -> D : {:?}", &pid0[..8], &fid[..8], name),
+        }
+    }
+
+    // sidecars fail to load, error already printed
+    for (name, _) in manifest.failed.iter() {
+        println!("{:8} {:8} -> E : {:?}", "", "", name);
+    }
+
+    if !moves.is_empty() {
+        println!("run nep-mv <old> <new> to keep history of moved file");
+    }
+
+    if !manifest.failed.is_empty() {
+        std::process::exit(1);
+    }
 }
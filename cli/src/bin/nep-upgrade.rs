@@ -0,0 +1,60 @@
+// nephrite-upgrade
+//
+// rewrite legacy .meta of project as current version, see proj/meta.rs
+use dotenv::dotenv;
+
+use nephrite4_common::proj;
+
+use proj::manifest::Manifest;
+use proj::meta;
+
+fn main() {
+    dotenv().ok();
+
+    env_logger::init();
+
+    let args = std::env::args_os().skip(1).
+        map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+
+    let wait = args.iter().any(|a| a == "--wait");
+
+    if args.iter().any(|a| a != "--wait") {
+        eprintln!("usage: nep-upgrade [--wait]");
+        std::process::exit(2);
+    }
+
+    let mut manifest = match Manifest::find(wait) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    println!("manifest {} object loaded.", manifest.anno_map.len());
+
+    // not loaded, left unconverted
+    let (mut n, mut fail) = (0, manifest.failed.len());
+
+    let annos = manifest.anno_map.iter_mut().chain(manifest.missing.iter_mut());
+
+    for (name, anno) in annos {
+        match anno.upgrade_meta() {
+            Ok(true) => {
+                println!("U {}", name);
+                n += 1;
+            },
+            Ok(false) => (),
+            Err(e) => {
+                println!("E {} -- {}", e, name);
+                fail += 1;
+            },
+        }
+    }
+
+    println!("{} upgraded to version {}, {} failed", n, meta::VERSION, fail);
+
+    if fail > 0 {
+        std::process::exit(1);
+    }
+}
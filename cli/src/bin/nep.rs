@@ -0,0 +1,134 @@
+// nephrite, front end of nep-* commands
+//
+// global flags are passed to the command by env, so each nep-* still
+// works standalone, unknown subcommand run nep-<name> like git
+
+use clap::{App, AppSettings, Arg, SubCommand};
+
+use nephrite4_common::conf;
+
+use std::env;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+// name, about, usage of builtin nep-* command
+const COMMANDS: &[(&str, &str, &str)] = &[
+    ("init", "create store, database schema and config",
+     "[--root <dir>] [--db <url>] [--no-db]"),
+    ("add", "add files to manifest, directory recursively",
+     "[--wait] [--dry-run] <path>..."),
+    ("status", "show changed files", "[--wait]"),
+    ("meta", "show or change annotation",
+     "[--wait] [--new] [<op>...] [--] <path>..."),
+    ("edit", "edit annotation in $EDITOR", "[--wait] <path>"),
+    ("commit", "commit changed annotation to store", "[--wait] [--rollback]"),
+    ("mv", "move file, keep its history", "[--wait] <old> <new>"),
+    ("rm", "stop tracking file, record removal in history",
+     "[--wait] [--force] <path>..."),
+    ("checkout", "restore file from store", "[--force] [--wait] <path|id>..."),
+    ("log", "show history of annotation", "[<path|id>...]"),
+    ("merge", "merge annotation changed by other host", "[--wait] [<path>...]"),
+    ("resolve", "mark merge conflict resolved",
+     "[--ours|--theirs] [--wait] <path>..."),
+    ("push", "copy changesets to other store", "<repo> <host>"),
+    ("pull", "copy changesets from other store", "<repo> <host>"),
+    ("fsck", "check store integrity", "[--no-dangling]"),
+    ("upgrade", "rewrite .meta of older version", "[--wait]"),
+    ("index", "index changesets to database", "[--migrate]"),
+    ("query", "search database", "[-n <num>] [--all] <patterns>..."),
+];
+
+// next to nep first, then PATH
+fn find_command(name: &str) -> Option<PathBuf> {
+    let exe = format!("nep-{}", name);
+
+    let sibling = env::current_exe().ok()
+        .and_then(|p| p.parent().map(|d| d.join(&exe)));
+
+    sibling.into_iter()
+        .chain(env::var_os("PATH").iter().flat_map(env::split_paths)
+               .map(|d| d.join(&exe)))
+        .find(|p| p.is_file())
+}
+
+fn run(name: &str, args: Vec<String>) -> ! {
+    let path = match find_command(name) {
+        Some(p) => p,
+        None => {
+            eprintln!("nep: '{}' is not a nep command, see 'nep help'", name);
+            process::exit(1);
+        },
+    };
+
+    // only return on error
+    let e = Command::new(&path).args(args).exec();
+
+    eprintln!("nep: fail to run {:?}, {}", path, e);
+    process::exit(1);
+}
+
+fn main() {
+    let usages: Vec<String> = COMMANDS.iter()
+        .map(|(name, _, usage)| format!("nep {} {}", name, usage)).collect();
+
+    let subcommands = COMMANDS.iter().zip(usages.iter()).map(|((name, about, _), usage)| {
+        SubCommand::with_name(name)
+            .about(*about)
+            .usage(usage.as_str())
+            .setting(AppSettings::TrailingVarArg)
+            .setting(AppSettings::AllowLeadingHyphen)
+            .arg(Arg::with_name("args").multiple(true).allow_hyphen_values(true))
+    });
+
+    let matches =
+        App::new("nep")
+        .about("file annotation with history")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::AllowExternalSubcommands)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(Arg::with_name("root")
+             .long("root")
+             .takes_value(true)
+             .help("store path, override root in config"))
+        .arg(Arg::with_name("db")
+             .long("db")
+             .takes_value(true)
+             .help("database url, override db.url in config"))
+        .arg(Arg::with_name("json")
+             .long("json")
+             .help("machine readable output"))
+        .arg(Arg::with_name("verbose")
+             .short("v")
+             .multiple(true)
+             .help("verbose log, -vv for debug"))
+        .subcommands(subcommands)
+        .get_matches();
+
+    if let Some(root) = matches.value_of("root") {
+        env::set_var(conf::NEPHRITE_ROOT, root);
+    }
+
+    if let Some(db) = matches.value_of("db") {
+        env::set_var(conf::NEPHRITE_DB_URL, db);
+    }
+
+    if matches.is_present("json") {
+        env::set_var(conf::NEPHRITE_JSON, "1");
+    }
+
+    match matches.occurrences_of("verbose") {
+        0 => (),
+        1 => env::set_var("RUST_LOG", "info"),
+        _ => env::set_var("RUST_LOG", "debug"),
+    }
+
+    let (name, sub) = matches.subcommand();
+
+    // external subcommand has args in ""
+    let args = sub.and_then(|m| m.values_of("args").or_else(|| m.values_of("")))
+        .map(|v| v.map(|x| x.to_string()).collect())
+        .unwrap_or_default();
+
+    run(name, args)
+}
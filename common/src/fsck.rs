@@ -0,0 +1,347 @@
+// repository integrity check
+
+use std::collections::BTreeSet;
+use std::io;
+
+use serde_json::json;
+
+use log::debug;
+
+use crate::error::*;
+use crate::git::{self, odb};
+use crate::hashsplit;
+use crate::proj::anno::Anno;
+use crate::store::{self, Store};
+use crate::util::Id;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    // not reachable from any changeset
+    Dangling,
+    // readable, but content is wrong
+    Corrupt,
+    // missing, or can not be read
+    Unreadable,
+}
+
+impl Kind {
+    pub fn str(&self) -> &'static str {
+        match self {
+            Kind::Dangling => "dangling",
+            Kind::Corrupt => "corrupt",
+            Kind::Unreadable => "unreadable",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub kind: Kind,
+    pub oid: Id,
+    // "changeset", "anno", "file", "gitmodules", or git type for dangling
+    pub what: String,
+    // changeset where problem found
+    pub cset: Option<Id>,
+    pub msg: String,
+}
+
+impl Problem {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "type": "problem",
+            "kind": self.kind.str(),
+            "oid": hex::encode(self.oid),
+            "what": self.what,
+            "cset": self.cset.map(hex::encode),
+            "msg": self.msg,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+    pub csets: usize,
+    pub annos: usize,
+    pub files: usize,
+    pub objects: usize,
+    // some changeset not walked, its objects would look dangling
+    pub dangling_skipped: bool,
+}
+
+impl Report {
+    pub fn summary_json(&self) -> serde_json::Value {
+        json!({
+            "type": "summary",
+            "csets": self.csets,
+            "annos": self.annos,
+            "files": self.files,
+            "objects": self.objects,
+            "dangling": self.count(Kind::Dangling),
+            "corrupt": self.count(Kind::Corrupt),
+            "unreadable": self.count(Kind::Unreadable),
+            "dangling_skipped": self.dangling_skipped,
+        })
+    }
+
+    pub fn count(&self, kind: Kind) -> usize {
+        self.problems.iter().filter(|p| p.kind == kind).count()
+    }
+}
+
+struct Fsck<'a> {
+    store: &'a Store,
+    reachable: BTreeSet<Id>,
+    // anno & file already checked
+    done: BTreeSet<Id>,
+    report: Report,
+}
+
+impl<'a> Fsck<'a> {
+    fn problem(&mut self, kind: Kind, oid: &Id, what: &str,
+               cset: Option<&Id>, msg: &str) {
+        debug!("fsck: {} {} {}, {}", kind.str(), what, hex::encode(oid), msg);
+
+        self.report.problems.push(Problem {
+            kind, oid: *oid, what: what.to_string(),
+            cset: cset.cloned(), msg: msg.to_string() });
+    }
+
+    // read & verify object hash
+    fn read(&mut self, oid: &Id) -> Result<(git::Type, Vec<u8>)> {
+        self.reachable.insert(*oid);
+
+        let (tp, data) = self.store.read_object(oid)?;
+
+        if odb::hash_object(tp, &data) != *oid {
+            return Err(Error::Simple(format!("hash mismatch, {} {}",
+                                             tp.str(), hex::encode(oid))))
+        }
+
+        Ok((tp, data))
+    }
+
+    // as read, but report problem, content returned even if corrupt
+    fn verify(&mut self, oid: &Id, what: &str, cset: &Id) -> Option<(git::Type, Vec<u8>)> {
+        self.reachable.insert(*oid);
+
+        match self.store.read_object(oid) {
+            Ok((tp, data)) => {
+                if odb::hash_object(tp, &data) != *oid {
+                    self.problem(Kind::Corrupt, oid, what, Some(cset),
+                                 &format!("hash mismatch, {}", tp.str()));
+                }
+                Some((tp, data))
+            },
+            Err(e) => {
+                self.problem(Kind::Unreadable, oid, what, Some(cset), &e.to_string());
+                None
+            },
+        }
+    }
+
+    fn check_file(&mut self, fid: &Id, size: Option<u64>, cset: &Id) {
+        if !self.done.insert(*fid) { return }
+        self.report.files += 1;
+
+        let mut bad: Option<(Kind, String)> = None;
+
+        let res = {
+            let reachable = &mut self.reachable;
+            let store = self.store;
+
+            hashsplit::join(fid, &mut io::sink(), &mut |oid| {
+                reachable.insert(*oid);
+                let (tp, data) = store.read_object(oid)?;
+
+                if odb::hash_object(tp, &data) != *oid {
+                    bad = Some((Kind::Corrupt,
+                                format!("hash mismatch in {}", hex::encode(oid))));
+                }
+
+                Ok((tp, data))
+            })
+        };
+
+        match res {
+            Err(e) => {
+                self.problem(Kind::Unreadable, fid, "file", Some(cset),
+                             &format!("join fail, {}", e));
+                self.mark_tree(fid);
+            },
+            Ok(sz) => {
+                if let Some((kind, msg)) = bad {
+                    self.problem(kind, fid, "file", Some(cset), &msg);
+                }
+                else if size.is_some_and(|s| s != sz) {
+                    self.problem(Kind::Corrupt, fid, "file", Some(cset),
+                                 &format!("size {} but anno record {}",
+                                          sz, size.unwrap()));
+                }
+            }
+        }
+    }
+
+    // join stop at first error, rest of tree is still referenced
+    fn mark_tree(&mut self, oid: &Id) {
+        self.reachable.insert(*oid);
+
+        let tid = match self.store.read_object(oid) {
+            Ok((git::Type::Commit, raw)) => match git::Commit::decode(&raw) {
+                Ok(c) => c.tree,
+                Err(_) => return,
+            },
+            Ok(_) => *oid,
+            Err(_) => return,
+        };
+
+        if tid != *oid { return self.mark_tree(&tid) }
+
+        if let Ok(tree) = self.store.read_tree(oid) {
+            for te in tree.iter() {
+                match te.mode {
+                    git::Type::Tree => self.mark_tree(&te.oid),
+                    _ => { self.reachable.insert(te.oid); },
+                }
+            }
+        }
+    }
+
+    fn check_anno(&mut self, aid: &Id, cset: &Id) {
+        if !self.done.insert(*aid) { return }
+        self.report.annos += 1;
+
+        let raw = match self.read(aid) {
+            Ok((git::Type::Commit, raw)) => raw,
+            Ok((tp, _)) => {
+                return self.problem(Kind::Corrupt, aid, "anno", Some(cset),
+                                    &format!("expect commit, found {}", tp.str()))
+            },
+            Err(e) => {
+                return self.problem(Kind::Unreadable, aid, "anno", Some(cset),
+                                    &e.to_string())
+            },
+        };
+
+        let anno = git::Commit::decode(&raw)
+            .and_then(|c| Anno::decode(&c.parent, &c.tree, &c.comment, true));
+
+        let anno = match anno {
+            Ok(x) => x,
+            Err(e) => {
+                return self.problem(Kind::Corrupt, aid, "anno", Some(cset),
+                                    &format!("decode fail, {}", e))
+            },
+        };
+
+        let size = match anno.data_get("size") {
+            Some(serde_cbor::Value::Integer(x)) => Some(*x as u64),
+            _ => None,
+        };
+
+        self.check_file(&anno.fid, size, cset);
+
+        // history is reachable too
+        for pid in anno.pid.iter() {
+            self.check_anno(pid, cset);
+        }
+    }
+
+    fn check_cset(&mut self, cid: &Id, tree: &git::Tree) {
+        // changeset from other host may share history
+        if !self.done.insert(*cid) { return }
+        self.report.csets += 1;
+        self.reachable.insert(*cid);
+
+        // walk read commit & tree without hash check
+        let tid = match self.verify(cid, "changeset", cid) {
+            Some((git::Type::Commit, raw)) => match git::Commit::decode(&raw) {
+                Ok(c) => Some(c.tree),
+                Err(e) => {
+                    self.problem(Kind::Corrupt, cid, "changeset", Some(cid),
+                                 &format!("decode fail, {}", e));
+                    None
+                },
+            },
+            Some((tp, _)) => {
+                self.problem(Kind::Corrupt, cid, "changeset", Some(cid),
+                             &format!("expect commit, found {}", tp.str()));
+                None
+            },
+            None => None,
+        };
+
+        if let Some(tid) = tid {
+            self.verify(&tid, "changeset", cid);
+        }
+
+        for te in tree.iter() {
+            if te.mode == git::Type::Commit {
+                self.check_anno(&te.oid, cid);
+            }
+            else if te.name == ".gitmodules" {
+                match self.read(&te.oid) {
+                    Ok((_, data)) => {
+                        if data != store::gen_gitmodules(tree).as_bytes() {
+                            self.problem(Kind::Corrupt, &te.oid, "gitmodules",
+                                         Some(cid), "not match changeset tree");
+                        }
+                    },
+                    Err(e) => self.problem(Kind::Unreadable, &te.oid,
+                                           "gitmodules", Some(cid),
+                                           &e.to_string()),
+                }
+            }
+        }
+    }
+}
+
+// check all changesets, and find dangling objects when `dangling`
+pub fn check(store: &Store, dangling: bool) -> Result<Report> {
+    let mut fsck = Fsck {
+        store,
+        reachable: BTreeSet::new(),
+        done: BTreeSet::new(),
+        report: Report::default(),
+    };
+
+    for cset in store.cset_all()? {
+        let tip = match store.git_show_ref(&store::ref_remote(&cset))? {
+            Some(x) => x,
+            None => continue,
+        };
+
+        match store.walk(&tip, None) {
+            Ok(list) => {
+                for (cid, tree) in list.iter() {
+                    fsck.check_cset(cid, tree);
+                }
+            },
+            Err(e) => {
+                fsck.problem(Kind::Unreadable, &tip, "changeset", None,
+                             &format!("walk '{}' fail, {}", cset, e));
+                fsck.report.dangling_skipped = dangling;
+            },
+        }
+    }
+
+    if dangling && !fsck.report.dangling_skipped {
+        let all = store.object_list()?;
+        fsck.report.objects = all.len();
+
+        let unref: Vec<Id> = all.into_iter()
+            .filter(|x| !fsck.reachable.contains(x))
+            .collect();
+
+        for oid in unref.iter() {
+            match store.read_object(oid) {
+                Ok((tp, _)) => fsck.problem(Kind::Dangling, oid, &tp.str(),
+                                            None, ""),
+                Err(e) => fsck.problem(Kind::Unreadable, oid, "object", None,
+                                       &e.to_string()),
+            }
+        }
+    }
+
+    Ok(fsck.report)
+}
@@ -1,9 +1,21 @@
 use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::str;
 
-use crate::util::Id;
+use crate::util::{self, Id};
+use crate::error::*;
+
+pub mod batch;
+pub mod config;
+pub mod odb;
+pub mod refs;
 
 pub type Oid = Id;
 
+// sha256 repository
+pub const OID_LEN: usize = 32;
+
 pub trait HasType {
     fn otype(&self) -> Type;
 }
@@ -51,14 +63,134 @@ impl Type {
             Type::Tag    => "tag",
         }.to_string()
     }
+
+    // parse object type from loose object header
+    pub fn from_name(s: &str) -> Option<Type> {
+        match s {
+            "commit" => Some(Type::Commit),
+            "tree"   => Some(Type::Tree),
+            "blob"   => Some(Type::blob()),
+            "tag"    => Some(Type::Tag),
+            _ => None,
+        }
+    }
+
+    // object type match, ignore blob mode
+    pub fn same(&self, other: &Type) -> bool {
+        self.str() == other.str()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Commit {
     pub parent: Vec<Oid>,
     pub tree: Oid,
+    // "name <email> time zone", empty when unknown
+    pub author: String,
+    pub committer: String,
     pub comment: String,
-    // TODO
+}
+
+impl Commit {
+    // same layout as git commit-tree
+    pub fn encode(&self) -> Vec<u8> {
+        let mut res = format!("tree {}\n", hex::encode(self.tree));
+
+        for p in self.parent.iter() {
+            res += &format!("parent {}\n", hex::encode(p));
+        }
+
+        res += &format!("author {}\ncommitter {}\n\n",
+                        self.author, self.committer);
+
+        // commit-tree -m always complete the last line
+        res += &self.comment;
+        if !self.comment.is_empty() && !self.comment.ends_with('\n') {
+            res.push('\n');
+        }
+
+        res.into_bytes()
+    }
+
+    pub fn decode(raw: &[u8]) -> Result<Commit> {
+        let buf = str::from_utf8(raw)
+            .map_err(|_| err_simple("commit is not utf-8"))?;
+
+        let (head, comment) = match buf.find("\n\n") {
+            Some(n) => (&buf[..n], &buf[n+2..]),
+            None => (buf.trim_end_matches('\n'), ""),
+        };
+
+        let mut res = Commit {
+            parent: vec![],
+            tree: [0;32],
+            author: String::new(),
+            committer: String::new(),
+            comment: comment.to_string(),
+        };
+
+        let mut has_tree = false;
+
+        for ln in head.lines() {
+            if let Some(x) = ln.strip_prefix("tree ") {
+                res.tree = parse_hex(x)?;
+                has_tree = true;
+            }
+            else if let Some(x) = ln.strip_prefix("parent ") {
+                res.parent.push(parse_hex(x)?);
+            }
+            else if let Some(x) = ln.strip_prefix("author ") {
+                res.author = x.to_string();
+            }
+            else if let Some(x) = ln.strip_prefix("committer ") {
+                res.committer = x.to_string();
+            }
+        }
+
+        if !has_tree { return err("commit without tree") }
+
+        Ok(res)
+    }
+}
+
+pub fn parse_hex(s: &str) -> Result<Oid> {
+    let mut res = [0;32];
+    hex::decode_to_slice(s.trim(), &mut res)
+        .map_err(|_| err_simple(&format!("invalid object id '{}'", s)))?;
+    Ok(res)
+}
+
+// same layout as "git init --bare --object-format=sha256"
+const INIT_CONFIG: &str = "[core]
+\trepositoryformatversion = 1
+\tfilemode = true
+\tbare = true
+[extensions]
+\tobjectformat = sha256
+";
+
+// create bare sha256 repository, false if already exist
+pub fn init_bare(git_dir: &str) -> Result<bool> {
+    let dir = Path::new(git_dir);
+
+    if dir.join("objects").is_dir() {
+        let conf = config::Config::read(git_dir);
+
+        return match conf.get("extensions.objectformat") {
+            Some("sha256") => Ok(false),
+            _ => err(&format!("{} is not a sha256 repository", git_dir)),
+        }
+    }
+
+    for d in ["objects/info", "objects/pack", "refs/heads", "refs/tags"].iter() {
+        fs::create_dir_all(dir.join(d))?;
+    }
+
+    fs::write(dir.join("HEAD"), "ref: refs/heads/master\n")?;
+    fs::write(dir.join("description"), "nephrite store\n")?;
+    fs::write(dir.join("config"), INIT_CONFIG)?;
+
+    Ok(true)
 }
 
 impl HasType for Commit { fn otype(&self) -> Type { Type::Commit }}
@@ -73,6 +205,53 @@ pub struct TreeEntry {
 // NOTE: Tree should be sorted by name
 pub type Tree = BTreeSet<TreeEntry>;
 
+/* format
+<mode> <name>\0<oid>
+<mode> <name>\0<oid>
+...
+ */
+pub fn encode_tree(tree: &Tree) -> Vec<u8> {
+    let mut res = vec![];
+
+    for entry in tree {
+        res.extend_from_slice(
+            format!("{:o} {}\0", entry.mode.mode(), entry.name).as_bytes());
+        res.extend_from_slice(&entry.oid);
+    }
+
+    res
+}
+
+pub fn decode_tree(raw: &[u8]) -> Result<Tree> {
+    let mut res = BTreeSet::new();
+    let mut rest = raw;
+
+    while !rest.is_empty() {
+        let sp = rest.iter().position(|b| *b == b' ')
+            .ok_or_else(|| err_simple("tree entry without mode"))?;
+        let nul = rest.iter().position(|b| *b == 0)
+            .ok_or_else(|| err_simple("tree entry without name"))?;
+
+        if nul < sp || rest.len() < nul + 1 + OID_LEN {
+            return err("truncated tree entry")
+        }
+
+        let mode = str::from_utf8(&rest[..sp]).ok()
+            .and_then(|m| i32::from_str_radix(m, 8).ok())
+            .ok_or_else(|| err_simple("invalid tree entry mode"))?;
+        let name = str::from_utf8(&rest[sp+1..nul])
+            .map_err(|_| err_simple("tree entry name is not utf-8"))?;
+
+        res.insert(TreeEntry { mode: Type::from_mode(mode),
+                               name: name.to_string(),
+                               oid: util::to_id(&rest[nul+1..nul+1+OID_LEN]) });
+
+        rest = &rest[nul+1+OID_LEN..];
+    }
+
+    Ok(res)
+}
+
 impl HasType for Tree { fn otype(&self) -> Type { Type::Tree }}
 
 #[derive(Debug, Clone)]
@@ -106,3 +285,45 @@ impl HasType for Object {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tree_round_trip() {
+        let mut tree = Tree::new();
+        tree.insert(TreeEntry { name: "0a".into(), oid: [1;32], mode: Type::Commit });
+        tree.insert(TreeEntry { name: ".gitmodules".into(), oid: [2;32], mode: Type::blob() });
+        tree.insert(TreeEntry { name: "sub".into(), oid: [3;32], mode: Type::Tree });
+
+        let raw = encode_tree(&tree);
+        assert!(raw.starts_with(b"100644 .gitmodules\0"));
+        assert_eq!(decode_tree(&raw).unwrap(), tree);
+    }
+
+    #[test]
+    fn test_commit_round_trip() {
+        let c = Commit {
+            parent: vec![[1;32], [2;32]],
+            tree: [3;32],
+            author: "a <a@b> 1600000000 +0800".into(),
+            committer: "a <a@b> 1600000000 +0800".into(),
+            comment: "name: x".into(),
+        };
+
+        let raw = c.encode();
+        assert!(raw.ends_with(b"+0800\n\nname: x\n"));
+
+        let c1 = Commit::decode(&raw).unwrap();
+        assert_eq!(c1.parent, c.parent);
+        assert_eq!(c1.tree, c.tree);
+        assert_eq!(c1.committer, c.committer);
+        assert_eq!(c1.comment, "name: x\n");
+
+        // empty message, as changeset commit
+        let c2 = Commit { comment: "".into(), ..c };
+        assert!(c2.encode().ends_with(b"+0800\n\n"));
+        assert_eq!(Commit::decode(&c2.encode()).unwrap().comment, "");
+    }
+}
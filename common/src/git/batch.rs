@@ -0,0 +1,170 @@
+// long-lived "git cat-file --batch" & "--batch-check" session
+//
+// only used when native object database can not find an object,
+// e.g. object in alternates
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use log::debug;
+
+use crate::error::*;
+
+use super::{Oid, Type};
+
+const ENV_GIT_DIR: &str = "GIT_DIR";
+
+// number of request write before read, keep response in pipe buffer
+const CHECK_CHUNK: usize = 256;
+
+struct CatFile {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl CatFile {
+    fn spawn(git_dir: &str, mode: &str) -> Result<CatFile> {
+        let mut child = Command::new("git")
+            .env(ENV_GIT_DIR, git_dir)
+            .arg("cat-file")
+            .arg(mode)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        debug!("spawn git cat-file {}", mode);
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+
+        Ok(CatFile { child, stdin, stdout })
+    }
+
+    fn request(&mut self, oid: &Oid) -> Result<()> {
+        self.stdin.write_all(format!("{}\n", hex::encode(oid)).as_bytes())?;
+        Ok(())
+    }
+
+    // "<oid> <type> <size>" or "<oid> missing"
+    fn header(&mut self) -> Result<Option<(Type, usize)>> {
+        let mut ln = String::new();
+        if self.stdout.read_line(&mut ln)? == 0 {
+            return err("git cat-file exit unexpectedly")
+        }
+
+        let f: Vec<&str> = ln.trim_end().split(' ').collect();
+
+        match f.as_slice() {
+            [_, "missing"] | [_, "ambiguous"] => Ok(None),
+            [_, tp, size] => {
+                let tp = Type::from_name(tp)
+                    .ok_or_else(|| err_simple(&format!("unknown object type {}", tp)))?;
+                let size = size.parse::<usize>()
+                    .map_err(|_| err_simple("invalid object size"))?;
+                Ok(Some((tp, size)))
+            },
+            _ => err(&format!("invalid git cat-file output '{}'", ln.trim_end())),
+        }
+    }
+
+    fn read(&mut self, oid: &Oid) -> Result<Option<(Type, Vec<u8>)>> {
+        self.request(oid)?;
+        self.stdin.flush()?;
+
+        let (tp, size) = match self.header()? {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        // content, then a LF
+        let mut buf = vec![0u8; size + 1];
+        self.stdout.read_exact(&mut buf)?;
+        buf.truncate(size);
+
+        Ok(Some((tp, buf)))
+    }
+
+    fn exists(&mut self, oids: &[Oid]) -> Result<Vec<bool>> {
+        let mut res = Vec::with_capacity(oids.len());
+
+        for chunk in oids.chunks(CHECK_CHUNK) {
+            for oid in chunk { self.request(oid)?; }
+            self.stdin.flush()?;
+
+            for _ in chunk { res.push(self.header()?.is_some()); }
+        }
+
+        Ok(res)
+    }
+}
+
+impl Drop for CatFile {
+    fn drop(&mut self) {
+        // stdin closed when drop, wait to avoid zombie
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+// spawn on first use, a clone start with no session
+pub struct Batch {
+    git_dir: String,
+    read: RefCell<Option<CatFile>>,
+    check: RefCell<Option<CatFile>>,
+}
+
+impl Batch {
+    pub fn new(git_dir: &str) -> Batch {
+        Batch {
+            git_dir: git_dir.to_string(),
+            read: RefCell::new(None),
+            check: RefCell::new(None),
+        }
+    }
+
+    fn with<T, F>(&self, cell: &RefCell<Option<CatFile>>, mode: &str, f: F) -> Result<T>
+        where F: FnOnce(&mut CatFile) -> Result<T>
+    {
+        let mut session = cell.borrow_mut();
+
+        if session.is_none() {
+            *session = Some(CatFile::spawn(&self.git_dir, mode)?);
+        }
+
+        let res = f(session.as_mut().unwrap());
+
+        // broken session, restart next time
+        if res.is_err() { *session = None; }
+
+        res
+    }
+
+    pub fn read(&self, oid: &Oid) -> Result<Option<(Type, Vec<u8>)>> {
+        self.with(&self.read, "--batch", |s| s.read(oid))
+    }
+
+    pub fn exists(&self, oids: &[Oid]) -> Result<Vec<bool>> {
+        if oids.is_empty() { return Ok(vec![]) }
+
+        self.with(&self.check, "--batch-check", |s| s.exists(oids))
+    }
+}
+
+impl Clone for Batch {
+    fn clone(&self) -> Self {
+        Batch::new(&self.git_dir)
+    }
+}
+
+impl fmt::Debug for Batch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Batch({:?}, read {}, check {})",
+               self.git_dir,
+               self.read.borrow().is_some(),
+               self.check.borrow().is_some())
+    }
+}
@@ -0,0 +1,166 @@
+// minimal git config reader, only for what we need: identity & format
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::*;
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    // "section.key" or "section.subsection.key", section & key lower case
+    map: BTreeMap<String, String>,
+}
+
+impl Config {
+    // read global config first, then repo config, later one win
+    pub fn read(git_dir: &str) -> Config {
+        let mut res = Config::default();
+
+        let mut files: Vec<PathBuf> = vec![];
+
+        let home = env::var("HOME").ok();
+        let xdg = env::var("XDG_CONFIG_HOME").ok()
+            .or_else(|| home.as_ref().map(|h| format!("{}/.config", h)));
+
+        if let Some(x) = xdg {
+            files.push(Path::new(&x).join("git/config"));
+        }
+
+        if let Some(h) = home {
+            files.push(Path::new(&h).join(".gitconfig"));
+        }
+
+        files.push(Path::new(git_dir).join("config"));
+
+        for f in files {
+            if let Ok(content) = fs::read_to_string(&f) {
+                res.parse(&content);
+            }
+        }
+
+        res
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.map.get(&key.to_lowercase()).map(|v| v.as_str())
+    }
+
+    fn parse(&mut self, content: &str) {
+        let mut section = String::new();
+
+        for ln in content.lines() {
+            let ln = ln.trim();
+
+            if ln.is_empty() || ln.starts_with('#') || ln.starts_with(';') {
+                continue
+            }
+
+            if ln.starts_with('[') {
+                // [section] or [section "sub"]
+                let inner = ln.trim_start_matches('[')
+                    .split(']').next().unwrap_or("").trim();
+
+                section = match inner.find(' ') {
+                    Some(n) => format!("{}.{}",
+                                       inner[..n].to_lowercase(),
+                                       inner[n..].trim().trim_matches('"')),
+                    None => inner.to_lowercase(),
+                };
+
+                continue
+            }
+
+            let (k, v) = match ln.find('=') {
+                Some(n) => (ln[..n].trim(), unquote(ln[n+1..].trim())),
+                // boolean key without value
+                None => (ln, "true".to_string()),
+            };
+
+            self.map.insert(format!("{}.{}", section, k.to_lowercase()), v);
+        }
+    }
+}
+
+fn unquote(v: &str) -> String {
+    let mut res = String::new();
+    let mut quoted = false;
+    let mut chars = v.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => match chars.next() {
+                Some('n') => res.push('\n'),
+                Some('t') => res.push('\t'),
+                Some(x) => res.push(x),
+                None => (),
+            },
+            '#' | ';' if !quoted => break,
+            _ => res.push(c),
+        }
+    }
+
+    res.trim_end().to_string()
+}
+
+// same as git, strip "crud" at start & end, drop '<', '>' & newline
+fn strip_crud(s: &str) -> String {
+    fn crud(c: char) -> bool {
+        (c as u32) <= 32 || ",:;<>\"\\'".contains(c)
+    }
+
+    s.trim_matches(crud)
+        .chars()
+        .filter(|c| *c != '<' && *c != '>' && *c != '\n')
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct Ident {
+    pub name: String,
+    pub email: String,
+}
+
+impl Ident {
+    // role is "AUTHOR" or "COMMITTER", GIT_<role>_NAME env first
+    pub fn get(conf: &Config, role: &str) -> Result<Ident> {
+        let name = env::var(format!("GIT_{}_NAME", role)).ok()
+            .or_else(|| conf.get("user.name").map(|x| x.to_string()));
+        let email = env::var(format!("GIT_{}_EMAIL", role)).ok()
+            .or_else(|| conf.get("user.email").map(|x| x.to_string()))
+            .or_else(|| env::var("EMAIL").ok());
+
+        match (name, email) {
+            (Some(n), Some(e)) => Ok(Ident { name: strip_crud(&n),
+                                             email: strip_crud(&e) }),
+            _ => err(&format!(
+                "{} identity unknown, please set user.name & user.email",
+                role.to_lowercase())),
+        }
+    }
+
+    // "name <email> <date>", date is "<unix time> <zone>"
+    pub fn line(&self, date: &str) -> String {
+        format!("{} <{}> {}", self.name, self.email, date)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut c = Config::default();
+        c.parse("[core]\n\trepositoryformatversion = 1\n\tbare\n\
+                 [Extensions]\n\tobjectFormat = sha256 ; comment\n\
+                 [user]\nname = \"A B\"\n");
+
+        assert_eq!(c.get("core.bare"), Some("true"));
+        assert_eq!(c.get("extensions.objectformat"), Some("sha256"));
+        assert_eq!(c.get("user.name"), Some("A B"));
+        assert_eq!(strip_crud(" <x@y>, "), "x@y");
+    }
+}
@@ -0,0 +1,488 @@
+// native object database: loose objects & packfiles of a sha256 repository
+
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use log::debug;
+
+use crate::error::*;
+use crate::util;
+
+use super::{Oid, Type, OID_LEN};
+
+const IDX_MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+const IDX_FANOUT: usize = 8;
+const IDX_NAMES: usize = IDX_FANOUT + 256 * 4;
+
+// pack object type
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+// "<type> <size>\0<data>"
+pub fn hash_object(tp: Type, data: &[u8]) -> Oid {
+    let mut hasher = Sha256::new();
+    let mut res = [0u8; 32];
+
+    hasher.input(format!("{} {}\0", tp.str(), data.len()).as_bytes());
+    hasher.input(data);
+    hasher.result(&mut res);
+
+    res
+}
+
+#[derive(Clone)]
+struct Pack {
+    path: PathBuf,
+    // whole .idx file content
+    idx: Vec<u8>,
+    num: usize,
+}
+
+impl fmt::Debug for Pack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pack({:?}, {})", self.path, self.num)
+    }
+}
+
+fn be32(b: &[u8], off: usize) -> u32 {
+    let mut x = [0u8; 4];
+    x.copy_from_slice(&b[off..off+4]);
+    u32::from_be_bytes(x)
+}
+
+fn be64(b: &[u8], off: usize) -> u64 {
+    let mut x = [0u8; 8];
+    x.copy_from_slice(&b[off..off+8]);
+    u64::from_be_bytes(x)
+}
+
+impl Pack {
+    fn open(idx_path: &Path) -> Result<Pack> {
+        let idx = fs::read(idx_path)?;
+
+        if idx.len() < IDX_NAMES || idx[..4] != IDX_MAGIC || be32(&idx, 4) != 2 {
+            return err(&format!("unsupported pack index {:?}", idx_path))
+        }
+
+        let num = be32(&idx, IDX_FANOUT + 255 * 4) as usize;
+
+        // names, crc32, offset
+        if idx.len() < IDX_NAMES + num * (OID_LEN + 8) {
+            return err(&format!("truncated pack index {:?}", idx_path))
+        }
+
+        // 64 bit offset table, for offset with msb set
+        let base = IDX_NAMES + num * (OID_LEN + 4);
+        let large = (0..num).map(|i| be32(&idx, base + i * 4))
+            .filter(|off| off & 0x8000_0000 != 0)
+            .map(|off| (off & 0x7fff_ffff) as usize + 1)
+            .max().unwrap_or(0);
+
+        if idx.len() < base + num * 4 + large * 8 {
+            return err(&format!("truncated pack index {:?}", idx_path))
+        }
+
+        Ok(Pack { path: idx_path.with_extension("pack"), idx, num })
+    }
+
+    fn name(&self, i: usize) -> &[u8] {
+        let off = IDX_NAMES + i * OID_LEN;
+        &self.idx[off..off+OID_LEN]
+    }
+
+    fn find(&self, oid: &Oid) -> Option<u64> {
+        let first = oid[0] as usize;
+        let mut lo = if first == 0 { 0 }
+                     else { be32(&self.idx, IDX_FANOUT + (first - 1) * 4) as usize };
+        let mut hi = be32(&self.idx, IDX_FANOUT + first * 4) as usize;
+
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.name(mid).cmp(&oid[..]) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(self.offset(mid)),
+            }
+        }
+
+        None
+    }
+
+    fn offset(&self, i: usize) -> u64 {
+        let base = IDX_NAMES + self.num * (OID_LEN + 4);
+        let off = be32(&self.idx, base + i * 4);
+
+        if off & 0x8000_0000 == 0 { return off as u64 }
+
+        let large = base + self.num * 4;
+        be64(&self.idx, large + (off & 0x7fff_ffff) as usize * 8)
+    }
+
+    fn oids(&self) -> Vec<Oid> {
+        (0..self.num).map(|i| util::to_id(self.name(i))).collect()
+    }
+}
+
+fn read_byte<R: Read>(rdr: &mut R) -> Result<u8> {
+    let mut b = [0u8; 1];
+    rdr.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn inflate<R: Read>(rdr: R, size: usize) -> Result<Vec<u8>> {
+    let mut res = Vec::with_capacity(size);
+    ZlibDecoder::new(rdr).take(size as u64 + 1).read_to_end(&mut res)?;
+
+    if res.len() != size { return err("inflated size mismatch") }
+
+    Ok(res)
+}
+
+// little endian base 128, as in delta header
+fn delta_size(delta: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut res = 0usize;
+    let mut shift = 0;
+
+    loop {
+        let c = *delta.get(*pos).ok_or_else(|| err_simple("truncated delta"))?;
+        *pos += 1;
+        if shift >= usize::BITS { return err("bad varint in delta") }
+        res |= ((c & 0x7f) as usize) << shift;
+        shift += 7;
+        if c & 0x80 == 0 { break }
+    }
+
+    Ok(res)
+}
+
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut pos = 0;
+
+    if delta_size(delta, &mut pos)? != base.len() {
+        return err("delta base size mismatch")
+    }
+
+    let size = delta_size(delta, &mut pos)?;
+    let mut res = Vec::with_capacity(size);
+
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+
+        if op & 0x80 != 0 {
+            // copy from base
+            let mut off = 0usize;
+            let mut len = 0usize;
+
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    off |= (*delta.get(pos).unwrap_or(&0) as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    len |= (*delta.get(pos).unwrap_or(&0) as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+
+            if len == 0 { len = 0x10000 }
+
+            if pos > delta.len() || off + len > base.len() {
+                return err("invalid delta copy")
+            }
+
+            res.extend_from_slice(&base[off..off+len]);
+        }
+        else if op != 0 {
+            // insert
+            let len = op as usize;
+            if pos + len > delta.len() { return err("truncated delta") }
+
+            res.extend_from_slice(&delta[pos..pos+len]);
+            pos += len;
+        }
+        else {
+            return err("invalid delta opcode")
+        }
+    }
+
+    if res.len() != size { return err("delta result size mismatch") }
+
+    Ok(res)
+}
+
+#[derive(Debug, Clone)]
+pub struct Odb {
+    // the "objects" dir
+    dir: PathBuf,
+
+    // loaded on first use
+    packs: RefCell<Option<Vec<Pack>>>,
+}
+
+impl Odb {
+    pub fn new(git_dir: &str) -> Odb {
+        Odb {
+            dir: Path::new(git_dir).join("objects"),
+            packs: RefCell::new(None),
+        }
+    }
+
+    fn loose_path(&self, oid: &Oid) -> PathBuf {
+        let h = hex::encode(oid);
+        self.dir.join(&h[..2]).join(&h[2..])
+    }
+
+    fn scan_packs(&self) -> Result<()> {
+        let mut res = vec![];
+        let dir = self.dir.join("pack");
+
+        if dir.is_dir() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|x| x == "idx") {
+                    res.push(Pack::open(&path)?);
+                }
+            }
+        }
+
+        debug!("scan_packs: {} packs", res.len());
+
+        *self.packs.borrow_mut() = Some(res);
+
+        Ok(())
+    }
+
+    fn read_loose(&self, oid: &Oid) -> Result<Option<(Type, Vec<u8>)>> {
+        let file = match File::open(self.loose_path(oid)) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut raw = vec![];
+        ZlibDecoder::new(BufReader::new(file)).read_to_end(&mut raw)?;
+
+        let nul = raw.iter().position(|b| *b == 0)
+            .ok_or_else(|| err_simple("invalid loose object header"))?;
+        let head = str::from_utf8(&raw[..nul])
+            .map_err(|_| err_simple("invalid loose object header"))?;
+
+        let mut it = head.splitn(2, ' ');
+        let tp = it.next().and_then(Type::from_name);
+        let size = it.next().and_then(|x| x.parse::<usize>().ok());
+
+        match (tp, size) {
+            (Some(tp), Some(size)) if size == raw.len() - nul - 1 => {
+                raw.drain(..nul+1);
+                Ok(Some((tp, raw)))
+            },
+            _ => err(&format!("corrupt loose object {}", hex::encode(oid))),
+        }
+    }
+
+    fn read_packed(&self, oid: &Oid) -> Result<Option<(Type, Vec<u8>)>> {
+        if self.packs.borrow().is_none() { self.scan_packs()?; }
+
+        let packs = self.packs.borrow();
+
+        for pack in packs.as_ref().unwrap().iter() {
+            if let Some(off) = pack.find(oid) {
+                let mut file = File::open(&pack.path)?;
+                return Ok(Some(self.read_pack_entry(&mut file, off)?));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn read_pack_entry(&self, file: &mut File, off: u64) -> Result<(Type, Vec<u8>)> {
+        file.seek(SeekFrom::Start(off))?;
+        let mut rdr = BufReader::new(&mut *file);
+
+        let mut c = read_byte(&mut rdr)?;
+        let tp = (c >> 4) & 7;
+        let mut size = (c & 0x0f) as usize;
+        let mut shift = 4;
+
+        while c & 0x80 != 0 {
+            if shift >= usize::BITS { return err("bad varint in pack entry") }
+            c = read_byte(&mut rdr)?;
+            size |= ((c & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+
+        let base_tp = match tp {
+            OBJ_COMMIT => return Ok((Type::Commit, inflate(rdr, size)?)),
+            OBJ_TREE   => return Ok((Type::Tree, inflate(rdr, size)?)),
+            OBJ_BLOB   => return Ok((Type::blob(), inflate(rdr, size)?)),
+            OBJ_TAG    => return Ok((Type::Tag, inflate(rdr, size)?)),
+            OBJ_OFS_DELTA => {
+                let mut c = read_byte(&mut rdr)?;
+                let mut rel = (c & 0x7f) as u64;
+
+                while c & 0x80 != 0 {
+                    if rel >= u64::MAX >> 7 { return err("bad varint in ofs-delta") }
+                    c = read_byte(&mut rdr)?;
+                    rel = ((rel + 1) << 7) | (c & 0x7f) as u64;
+                }
+
+                if rel > off { return err("invalid ofs-delta base") }
+
+                let delta = inflate(rdr, size)?;
+                let (tp, base) = self.read_pack_entry(file, off - rel)?;
+                return Ok((tp, apply_delta(&base, &delta)?))
+            },
+            OBJ_REF_DELTA => {
+                let mut base_id = [0u8; OID_LEN];
+                rdr.read_exact(&mut base_id)?;
+                base_id
+            },
+            _ => return err(&format!("unknown pack object type {}", tp)),
+        };
+
+        let delta = inflate(rdr, size)?;
+        let (tp, base) = self.lookup(&base_tp)?
+            .ok_or_else(|| err_simple(&format!("missing delta base {}",
+                                               hex::encode(base_tp))))?;
+
+        Ok((tp, apply_delta(&base, &delta)?))
+    }
+
+    fn lookup(&self, oid: &Oid) -> Result<Option<(Type, Vec<u8>)>> {
+        if let Some(x) = self.read_loose(oid)? { return Ok(Some(x)) }
+        self.read_packed(oid)
+    }
+
+    pub fn read_opt(&self, oid: &Oid) -> Result<Option<(Type, Vec<u8>)>> {
+        if let Some(x) = self.lookup(oid)? { return Ok(Some(x)) }
+
+        // maybe new pack since last scan
+        self.scan_packs()?;
+        self.read_packed(oid)
+    }
+
+    pub fn read(&self, oid: &Oid) -> Result<(Type, Vec<u8>)> {
+        self.read_opt(oid)?.ok_or_else(
+            || err_simple(&format!("object {} not found", hex::encode(oid))))
+    }
+
+    pub fn contains(&self, oid: &Oid) -> Result<bool> {
+        if self.loose_path(oid).is_file() { return Ok(true) }
+
+        if self.packs.borrow().is_none() { self.scan_packs()?; }
+
+        let found = self.packs.borrow().as_ref().unwrap()
+            .iter().any(|p| p.find(oid).is_some());
+
+        Ok(found)
+    }
+
+    pub fn write(&self, tp: Type, data: &[u8]) -> Result<Oid> {
+        let oid = hash_object(tp, data);
+
+        if self.contains(&oid)? { return Ok(oid) }
+
+        let path = self.loose_path(&oid);
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir)?;
+
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos()).unwrap_or(0);
+        let tmp = dir.join(format!("tmp_obj_{}_{}", process::id(), nanos));
+
+        {
+            let file = File::create(&tmp)?;
+            let mut enc = ZlibEncoder::new(file, Compression::default());
+            enc.write_all(format!("{} {}\0", tp.str(), data.len()).as_bytes())?;
+            enc.write_all(data)?;
+            enc.finish()?.sync_all()?;
+        }
+
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o444))?;
+        fs::rename(&tmp, &path)?;
+
+        debug!("write {} {}", tp.str(), hex::encode(oid));
+
+        Ok(oid)
+    }
+
+    // all object ids, loose & packed
+    pub fn list(&self) -> Result<Vec<Oid>> {
+        let mut res = vec![];
+
+        if self.dir.is_dir() {
+            for entry in fs::read_dir(&self.dir)? {
+                let entry = entry?;
+                let prefix = entry.file_name().to_string_lossy().to_string();
+                if prefix.len() != 2 || !entry.path().is_dir() { continue }
+
+                for obj in fs::read_dir(entry.path())? {
+                    let name = obj?.file_name().to_string_lossy().to_string();
+                    if name.len() != OID_LEN * 2 - 2 { continue }
+
+                    if let Ok(id) = super::parse_hex(&(prefix.clone() + &name)) {
+                        res.push(id);
+                    }
+                }
+            }
+        }
+
+        self.scan_packs()?;
+        for pack in self.packs.borrow().as_ref().unwrap().iter() {
+            res.extend(pack.oids());
+        }
+
+        res.sort();
+        res.dedup();
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash_object() {
+        // printf '' | git hash-object --object-format=sha256 -t blob --stdin
+        assert_eq!(
+            hex::encode(hash_object(Type::blob(), b"")),
+            "473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813");
+    }
+
+    #[test]
+    fn test_apply_delta() {
+        let base = b"hello, world";
+        // src 12, dst 12, copy(0, 7), insert "there"
+        let delta = [12, 12, 0x80 | 0x10, 7, 5, b't', b'h', b'e', b'r', b'e'];
+        assert_eq!(apply_delta(base, &delta[..]).unwrap(), b"hello, there");
+
+        assert!(apply_delta(b"x", &delta[..]).is_err());
+
+        // too long size varint
+        assert!(apply_delta(base, &[0xff; 12]).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+// native refs: loose ref files & packed-refs
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::error::*;
+
+use super::{parse_hex, Oid};
+
+fn read_packed(git_dir: &str) -> Result<BTreeMap<String, Oid>> {
+    let mut res = BTreeMap::new();
+
+    let content = match fs::read_to_string(Path::new(git_dir).join("packed-refs")) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(res),
+        Err(e) => return Err(e.into()),
+    };
+
+    for ln in content.lines() {
+        // header, or peeled tag
+        if ln.starts_with('#') || ln.starts_with('^') { continue }
+
+        let mut i = ln.splitn(2, ' ');
+        if let (Some(id), Some(name)) = (i.next(), i.next()) {
+            res.insert(name.to_string(), parse_hex(id)?);
+        }
+    }
+
+    Ok(res)
+}
+
+fn read_loose(git_dir: &str, name: &str, depth: usize) -> Result<Option<Oid>> {
+    let path = Path::new(git_dir).join(name);
+
+    let content = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        // may be a dir, e.g. "refs/remotes/x" for "refs/remotes/x/y"
+        Err(_) if path.is_dir() => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let content = content.trim();
+
+    if let Some(target) = content.strip_prefix("ref: ") {
+        if depth > 5 { return err(&format!("symbolic ref loop at {}", name)) }
+        return read(git_dir, target.trim(), depth + 1)
+    }
+
+    Ok(Some(parse_hex(content)?))
+}
+
+fn read(git_dir: &str, name: &str, depth: usize) -> Result<Option<Oid>> {
+    if let Some(id) = read_loose(git_dir, name, depth)? { return Ok(Some(id)) }
+
+    Ok(read_packed(git_dir)?.remove(name))
+}
+
+// same as "git show-ref --verify --hash <name>"
+pub fn read_ref(git_dir: &str, name: &str) -> Result<Option<Oid>> {
+    read(git_dir, name, 0)
+}
+
+fn walk_loose(git_dir: &str, dir: &str, res: &mut BTreeMap<String, Oid>)
+              -> Result<()> {
+    let path = Path::new(git_dir).join(dir);
+    if !path.is_dir() { return Ok(()) }
+
+    for entry in fs::read_dir(&path)? {
+        let entry = entry?;
+        let name = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+
+        if entry.file_type()?.is_dir() {
+            walk_loose(git_dir, &name, res)?;
+        }
+        else if !name.ends_with(".lock") {
+            if let Some(id) = read_loose(git_dir, &name, 0)? {
+                res.insert(name, id);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// same as "git show-ref", all refs under "refs/"
+pub fn list_refs(git_dir: &str) -> Result<BTreeMap<String, Oid>> {
+    let mut res = read_packed(git_dir)?;
+
+    // loose ref override packed one
+    walk_loose(git_dir, "refs", &mut res)?;
+
+    Ok(res)
+}
+
+// same as "git update-ref <name> <oid>", without reflog
+pub fn update_ref(git_dir: &str, name: &str, oid: &Oid) -> Result<()> {
+    let path = Path::new(git_dir).join(name);
+    let lock = Path::new(git_dir).join(format!("{}.lock", name));
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(&lock) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists =>
+            return err(&format!("ref {} is locked, remove {:?} if no \
+                                 other process is running", name, lock)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let res = file.write_all(format!("{}\n", hex::encode(oid)).as_bytes())
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&lock, &path));
+
+    if let Err(e) = res {
+        fs::remove_file(&lock).ok();
+        return Err(e.into())
+    }
+
+    Ok(())
+}
+
+// same as "git update-ref -d <name>", remove both loose & packed
+pub fn delete_ref(git_dir: &str, name: &str) -> Result<()> {
+    match fs::remove_file(Path::new(git_dir).join(name)) {
+        Ok(_) => (),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e.into()),
+    }
+
+    let packed = Path::new(git_dir).join("packed-refs");
+    let content = match fs::read_to_string(&packed) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    // keep peeled line only when its ref kept
+    let mut keep = true;
+    let mut res = String::new();
+
+    for ln in content.lines() {
+        if !ln.starts_with('#') && !ln.starts_with('^') {
+            keep = ln.split_once(' ').map(|x| x.1) != Some(name);
+        }
+
+        if keep || ln.starts_with('#') {
+            res += ln;
+            res.push('\n');
+        }
+    }
+
+    if res.len() == content.len() { return Ok(()) }
+
+    let lock = Path::new(git_dir).join("packed-refs.lock");
+
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(&lock) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists =>
+            return err(&format!("packed-refs is locked, remove {:?} if no \
+                                 other process is running", lock)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let res = file.write_all(res.as_bytes())
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&lock, &packed));
+
+    if let Err(e) = res {
+        fs::remove_file(&lock).ok();
+        return Err(e.into())
+    }
+
+    Ok(())
+}
@@ -0,0 +1,284 @@
+// content defined chunking, same algorithm & tree layout as "bup split -t"
+
+use std::io::{Read, Write};
+
+use crate::error::*;
+use crate::git::{self, Oid};
+
+const BLOB_BITS: u32 = 13;
+const BLOB_MASK: u32 = (1 << BLOB_BITS) - 1;
+const BLOB_MAX: usize = 8192 * 4;
+const BLOB_READ_SIZE: usize = 1024 * 1024;
+
+const WINDOW_SIZE: usize = 1 << 6;
+const CHAR_OFFSET: u32 = 31;
+
+const FANOUT_BITS: u32 = 4;
+const MAX_PER_TREE: usize = 256;
+
+struct Rollsum {
+    s1: u32,
+    s2: u32,
+    window: [u8; WINDOW_SIZE],
+    wofs: usize,
+}
+
+impl Rollsum {
+    fn new() -> Rollsum {
+        Rollsum {
+            s1: WINDOW_SIZE as u32 * CHAR_OFFSET,
+            s2: WINDOW_SIZE as u32 * (WINDOW_SIZE as u32 - 1) * CHAR_OFFSET,
+            window: [0; WINDOW_SIZE],
+            wofs: 0,
+        }
+    }
+
+    fn roll(&mut self, ch: u8) {
+        let drop = self.window[self.wofs] as u32;
+        let add = ch as u32;
+
+        self.s1 = self.s1.wrapping_add(add).wrapping_sub(drop);
+        self.s2 = self.s2.wrapping_add(self.s1)
+            .wrapping_sub(WINDOW_SIZE as u32 * (drop + CHAR_OFFSET));
+
+        self.window[self.wofs] = ch;
+        self.wofs = (self.wofs + 1) % WINDOW_SIZE;
+    }
+
+    fn digest(&self) -> u32 {
+        (self.s1 << 16) | (self.s2 & 0xffff)
+    }
+}
+
+// split point & bits, as bupsplit_find_ofs
+fn find_ofs(buf: &[u8]) -> Option<(usize, u32)> {
+    let mut r = Rollsum::new();
+
+    for (i, ch) in buf.iter().enumerate() {
+        r.roll(*ch);
+
+        if r.s2 & BLOB_MASK == BLOB_MASK {
+            let mut rsum = r.digest() >> BLOB_BITS;
+            let mut bits = BLOB_BITS;
+
+            loop {
+                rsum >>= 1;
+                if rsum & 1 == 0 { break }
+                bits += 1;
+            }
+
+            return Some((i + 1, bits));
+        }
+    }
+
+    None
+}
+
+// (mode, oid, size)
+type Item = (git::Type, Oid, u64);
+
+// entry name is hex offset, padded to same width
+fn make_tree<F>(items: &[Item], write: &mut F) -> Result<(Oid, u64)>
+    where F: FnMut(git::Type, &[u8]) -> Result<Oid>
+{
+    let total: u64 = items.iter().map(|x| x.2).sum();
+    let width = format!("{:x}", total).len();
+
+    let mut tree = git::Tree::new();
+    let mut ofs = 0u64;
+
+    for (mode, oid, size) in items.iter() {
+        tree.insert(git::TreeEntry { name: format!("{:0w$x}", ofs, w = width),
+                                     oid: *oid,
+                                     mode: *mode });
+        ofs += size;
+    }
+
+    Ok((write(git::Type::Tree, &git::encode_tree(&tree))?, total))
+}
+
+fn squish<F>(stacks: &mut Vec<Vec<Item>>, n: usize, write: &mut F) -> Result<()>
+    where F: FnMut(git::Type, &[u8]) -> Result<Oid>
+{
+    let mut i = 0;
+
+    while i < n || stacks[i].len() >= MAX_PER_TREE {
+        while stacks.len() <= i + 1 { stacks.push(vec![]); }
+
+        if stacks[i].len() == 1 {
+            let x = stacks[i].pop().unwrap();
+            stacks[i + 1].push(x);
+        }
+        else if !stacks[i].is_empty() {
+            let (oid, size) = make_tree(&stacks[i], write)?;
+            stacks[i + 1].push((git::Type::Tree, oid, size));
+        }
+
+        stacks[i].clear();
+        i += 1;
+    }
+
+    Ok(())
+}
+
+fn read_block<R: Read>(rdr: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut n = 0;
+
+    while n < buf.len() {
+        let sz = rdr.read(&mut buf[n..])?;
+        if sz == 0 { break }
+        n += sz;
+    }
+
+    Ok(n)
+}
+
+// split content into blobs, return top tree, write(type, data) save object
+pub fn split<R, F>(rdr: &mut R, write: &mut F) -> Result<Oid>
+    where R: Read, F: FnMut(git::Type, &[u8]) -> Result<Oid>
+{
+    let mut stacks: Vec<Vec<Item>> = vec![vec![]];
+    let mut buf: Vec<u8> = vec![];
+    let mut block = vec![0u8; BLOB_READ_SIZE];
+
+    let emit = |stacks: &mut Vec<Vec<Item>>, blob: &[u8], level: u32,
+                    write: &mut F| -> Result<()> {
+        let oid = write(git::Type::blob(), blob)?;
+        stacks[0].push((git::Type::blob(), oid, blob.len() as u64));
+        squish(stacks, level as usize, write)
+    };
+
+    loop {
+        let n = read_block(rdr, &mut block)?;
+        if n == 0 { break }
+
+        buf.extend_from_slice(&block[..n]);
+
+        let mut start = 0;
+
+        while let Some((ofs, bits)) = find_ofs(&buf[start..]) {
+            let (ofs, level) = if ofs > BLOB_MAX { (BLOB_MAX, 0) }
+                               else { (ofs, (bits - BLOB_BITS) / FANOUT_BITS) };
+
+            emit(&mut stacks, &buf[start..start + ofs], level, write)?;
+            start += ofs;
+        }
+
+        // limit max blob size
+        while buf.len() - start >= BLOB_MAX {
+            emit(&mut stacks, &buf[start..start + BLOB_MAX], 0, write)?;
+            start += BLOB_MAX;
+        }
+
+        buf.drain(..start);
+    }
+
+    if !buf.is_empty() {
+        emit(&mut stacks, &buf, 0, write)?;
+    }
+
+    let n = stacks.len() - 1;
+    squish(&mut stacks, n, write)?;
+
+    Ok(make_tree(stacks.last().unwrap(), write)?.0)
+}
+
+// fid of content without write any object, e.g. to find a moved file
+pub fn calc_id<R: Read>(rdr: &mut R) -> Result<Oid> {
+    split(rdr, &mut |tp, data| Ok(git::odb::hash_object(tp, data)))
+}
+
+// write content of blob, or tree of blobs, or commit point to such tree
+pub fn join<W, F>(oid: &Oid, out: &mut W, read: &mut F) -> Result<u64>
+    where W: Write, F: FnMut(&Oid) -> Result<(git::Type, Vec<u8>)>
+{
+    let (tp, data) = read(oid)?;
+
+    match tp {
+        git::Type::Blob(_) => {
+            out.write_all(&data)?;
+            Ok(data.len() as u64)
+        },
+        git::Type::Tree => {
+            let mut size = 0;
+
+            for entry in git::decode_tree(&data)? {
+                match entry.mode {
+                    git::Type::Tree | git::Type::Blob(_) =>
+                        size += join(&entry.oid, out, read)?,
+                    _ => return err(&format!("unexpected entry '{}' in {}",
+                                             entry.name, hex::encode(oid))),
+                }
+            }
+
+            Ok(size)
+        },
+        git::Type::Commit => {
+            let commit = git::Commit::decode(&data)?;
+            join(&commit.tree, out, read)
+        },
+        git::Type::Tag => err(&format!("can not join tag {}", hex::encode(oid))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn gen(len: usize) -> Vec<u8> {
+        // simple lcg, deterministic content
+        let mut x = 12345u32;
+        (0..len).map(|_| {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            (x >> 16) as u8
+        }).collect()
+    }
+
+    fn round_trip(data: &[u8]) -> (Oid, BTreeMap<Oid, (git::Type, Vec<u8>)>) {
+        let mut objs = BTreeMap::new();
+        let tid = split(&mut &data[..], &mut |tp, d: &[u8]| {
+            let oid = git::odb::hash_object(tp, d);
+            objs.insert(oid, (tp, d.to_vec()));
+            Ok(oid)
+        }).unwrap();
+
+        let mut out = vec![];
+        let size = join(&tid, &mut out, &mut |oid| Ok(objs[oid].clone())).unwrap();
+
+        assert_eq!(size as usize, data.len());
+        assert_eq!(out, data);
+
+        (tid, objs)
+    }
+
+    #[test]
+    fn test_split_join() {
+        let (_, objs) = round_trip(b"");
+        assert_eq!(objs.len(), 1);
+
+        let (tid, objs) = round_trip(b"hello");
+        let tree = git::decode_tree(&objs[&tid].1).unwrap();
+        assert_eq!(tree.iter().next().unwrap().name, "0");
+
+        let data = gen(3 * BLOB_READ_SIZE + 12345);
+        let (tid, objs) = round_trip(&data);
+
+        // no blob exceed max size
+        assert!(objs.values().all(|(tp, d)| !tp.same(&git::Type::blob()) ||
+                                  d.len() <= BLOB_MAX));
+
+        // deterministic
+        assert_eq!(round_trip(&data).0, tid);
+    }
+
+    #[test]
+    fn test_find_ofs() {
+        let data = gen(1 << 20);
+        let (ofs, bits) = find_ofs(&data).unwrap();
+        assert!(ofs > 0 && bits >= BLOB_BITS);
+
+        // boundary depend only on content before it
+        assert_eq!(find_ofs(&data[..ofs]), Some((ofs, bits)));
+    }
+}
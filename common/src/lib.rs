@@ -4,6 +4,10 @@ pub mod util;
 pub mod store;
 pub mod error;
 pub mod git;
+pub mod hashsplit;
+pub mod fsck;
+pub mod sync;
+pub mod output;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,84 @@
+// machine readable output, one json object per line (NDJSON)
+//
+// enabled by --json of each command, or `nep --json` ($NEPHRITE_JSON=1),
+// then stdout has records only, human message go to stderr
+//
+// schema is stable: every record has "type", id is full lowercase hex,
+// path is relative to project root, time is unix millisecond; fields may
+// be added, never renamed or removed
+//
+// nep-status
+//   {"type":"status","path":s,
+//    "status":"ready"|"meta"|"file"|"missing"|"error",
+//    "fid":id|null,"pid":[id],"error":s|null}   fid null if sidecars
+//                                              fail to load, exit 1
+//   {"type":"move","from":s,"to":s}   missing file found at untracked path
+//   {"type":"journal","path":s}     interrupted commit, path of journal
+//
+// nep-meta
+//   {"type":"meta","path":s,"changed":n,"fid":id,"pid":[id],"attr":{..}}
+//   {"type":"error","path":s,"msg":s}
+//
+// nep-commit
+//   {"type":"anno","path":s,"aid":id,"resume":bool}
+//   {"type":"commit","cset":id|null,"tree":id|null,"annos":n}
+//   {"type":"rollback","annos":n}          with --rollback
+//
+// nep-query
+//   {"type":"result","id":id,"fid":id,"attr":[{..}]}
+//   {"type":"selection","name":s,"count":n,"append":bool}
+//
+// nep-index
+//   {"type":"anno","cset":id,"aid":id,"state":"imported"|"exist",
+//    "diverged":id|null}
+//   {"type":"error","cset":s,"msg":s}
+//   {"type":"index","total":n}
+//
+// nep-fsck
+//   {"type":"problem","kind":"dangling"|"corrupt"|"unreadable","oid":id,
+//    "what":s,"cset":id|null,"msg":s}
+//   {"type":"summary","csets":n,"annos":n,"files":n,"objects":n,
+//    "dangling":n,"corrupt":n,"unreadable":n,"dangling_skipped":bool}
+
+use serde_cbor::value as cv;
+use serde_json::{json, Value};
+
+use std::collections::BTreeMap;
+use std::env;
+
+use crate::conf;
+use crate::proj::anno::St;
+use crate::util::Id;
+
+pub const JSON_FLAG: &str = "--json";
+
+// flag of this command, or global one from nep
+pub fn enabled(args: &[String]) -> bool {
+    args.iter().any(|a| a == JSON_FLAG) ||
+        env::var(conf::NEPHRITE_JSON).is_ok_and(|v| v == "1")
+}
+
+pub fn id(id: &Id) -> Value {
+    json!(hex::encode(id))
+}
+
+pub fn ids(ids: &[Id]) -> Value {
+    Value::Array(ids.iter().map(id).collect())
+}
+
+// anno data, null if not representable
+pub fn attr(data: &BTreeMap<String, cv::Value>) -> Value {
+    serde_json::to_value(data).unwrap_or(Value::Null)
+}
+
+pub fn status(st: St) -> &'static str {
+    match st {
+        St::Ready => "ready",
+        St::MMeta => "meta",
+        St::MFile => "file",
+    }
+}
+
+pub fn emit(record: Value) {
+    println!("{}", record);
+}
@@ -1,4 +1,97 @@
 pub mod manifest;
 pub mod anno;
+pub mod merge;
+pub mod lock;
+pub mod journal;
+pub mod ignore;
+pub mod cache;
+pub mod schema;
+pub mod op;
+pub mod canon;
+pub mod meta;
+
+use crate::error::*;
+
+use std::env;
+use std::path::{Component, Path, PathBuf};
 
 pub const MANIFEST: &'static str = ".manifest";
+
+// nearest dir contain MANIFEST, search upward like git
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub root: PathBuf,
+}
+
+// remove "." & "..", without touch file system, path may not exist
+fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+
+    for c in path.components() {
+        match c {
+            Component::CurDir => (),
+            Component::ParentDir => { res.pop(); },
+            x => res.push(x.as_os_str()),
+        }
+    }
+
+    res
+}
+
+impl Project {
+    pub fn find() -> Result<Project> {
+        let cwd = env::current_dir()?;
+
+        match Self::find_from(&cwd) {
+            Some(x) => Ok(x),
+            None => err(&format!("not in a project, no {} in {:?} or any parent",
+                                 MANIFEST, cwd)),
+        }
+    }
+
+    pub fn find_from(dir: &Path) -> Option<Project> {
+        dir.ancestors()
+            .find(|d| d.join(MANIFEST).is_dir())
+            .map(|d| Project { root: d.to_path_buf() })
+    }
+
+    pub fn mdir(&self) -> String {
+        self.root.join(MANIFEST).to_string_lossy().to_string()
+    }
+
+    // path argument relative to cwd, to path relative to project root
+    pub fn rpath(&self, arg: &str) -> Result<String> {
+        match self.rdir(arg)? {
+            r if r.is_empty() => err(&format!("{} is project root", arg)),
+            r => Ok(r),
+        }
+    }
+
+    // as rpath, but "" for project root, for directory argument
+    pub fn rdir(&self, arg: &str) -> Result<String> {
+        let abs = normalize(&env::current_dir()?.join(arg));
+
+        match abs.strip_prefix(&self.root) {
+            Ok(r) if r.starts_with(MANIFEST) =>
+                err(&format!("{} is inside {}", arg, MANIFEST)),
+            Ok(r) => Ok(r.to_string_lossy().to_string()),
+            Err(_) => err(&format!("{} is outside project {:?}", arg, self.root)),
+        }
+    }
+
+    // path of file in project
+    pub fn path(&self, rpath: &str) -> PathBuf {
+        self.root.join(rpath)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize(Path::new("/a/b/../../..")), Path::new("/"));
+    }
+}
@@ -4,6 +4,12 @@ use crate::util;
 use crate::util::Id;
 
 use crate::error::*;
+use crate::proj::merge;
+use crate::proj::cache;
+use crate::proj::canon;
+use crate::proj::meta::{self, Meta};
+use crate::proj::op::{self, Op};
+use crate::proj::schema::Schema;
 
 use std::io;
 use std::io::prelude::*;
@@ -26,9 +32,11 @@ use std::str;
 
 use filetime::FileTime;
 
-use lazy_static::lazy_static;
 use log::debug;
 
+// reserved key of tombstone commit, written by nep-rm only
+pub const REMOVED: &str = "_removed";
+
 #[derive(PartialEq, Eq)]
 pub enum St {
     Ready, // '.'
@@ -50,6 +58,7 @@ pub struct Anno {
     pub pid: Vec<Id>, // may empty
     pub fid: Id, // use fid when mtime match
     pub anno_hash: Id, // use to check whether yaml/meta need update
+    pub content: Option<meta::Content>, // file when saved, none if legacy meta
 
     // yaml
     pub data: BTreeMap<String, cv::Value>,
@@ -60,153 +69,23 @@ pub struct Anno {
     rpath: String,
 }
 
-lazy_static! {
-    static ref PREDEFINED: Vec<String> = {
-        let v = vec!["name", "type", "size", "note",
-                     "rate", "tag", "node", "level"];
-        v.iter().map(|v| v.to_string()).collect()
-    };
-}
-
 impl Anno {
-    pub fn proc_op(&mut self, ops: &[String], allow_new: bool) -> u64 {
-        // +xxx, -xxx -> tag+xxx, tag-xxx
-        // xxx=xxx -> set kv
-        // xxx+xxx -> append
-        // xxx-xxx -> remove
-        fn add(data: &mut BTreeMap<String, cv::Value>,
-               key: &str, val: &str, an: bool) -> bool {
-            let key_s = key.to_string();
-            let mut res = true;
-
-            let exists = data.contains_key(key);
-            if !exists && !PREDEFINED.contains(&key_s) && !an {
-                return false;
-            }
-
-            let vstr = cv::Value::Text(val.to_string());
-
-            if exists {
-                let mut v = data.remove(&key_s).unwrap();
-
-                match v {
-                    cv::Value::Array(ref mut a) => {
-                        for z in a.iter() {
-                            if &vstr == z {
-                                res = false;
-                            }
-                        }
-
-                        if res {
-                            a.push(vstr);
-                        }
-                    },
-                    _ => {
-                        if vstr == v {
-                            res = false;
-                        }
-
-                        if res {
-                            v = cv::Value::Array(vec![v, vstr]);
-                        }
-                    }
-                }
-
-                data.insert(key_s, v);
-            }
-            else {
-                data.insert(key_s, vstr);
-            }
-
-            res
-        }
-
-        fn set(data: &mut BTreeMap<String, cv::Value>,
-               key: &str, val: &str, an: bool) -> bool {
-            let exists = data.contains_key(key);
-            let key_s = key.to_string();
-            if !exists && !PREDEFINED.contains(&key_s) && !an {
-                return false;
-            }
-            let vstr = cv::Value::Text(val.to_string());
-            data.insert(key_s, vstr);
-            true
-        }
-
-        fn del(data: &mut BTreeMap<String, cv::Value>,
-               key: &str, val: &str) -> bool {
-            let exists = data.contains_key(key);
-            if !exists { return false; }
-
-            let key_s = key.to_string();
-
-            let old = data.remove(key).unwrap();
-            let vstr = cv::Value::Text(val.to_string());
-
-            match &old {
-                cv::Value::Text(ref _t) => {
-                    if _t == &val { return true; }
-
-                    data.insert(key.to_string(), old);
-                    return false;
-                },
-
-                // TODO: in-place modify old
-                cv::Value::Array(_x) => {
-                    let mut a: Vec<cv::Value> = vec![];
-                    let mut res = false;
+    // ops as nep-meta argument, see op.rs, return count of changed
+    pub fn proc_op(&mut self, schema: &Schema, ops: &[String], allow_new: bool)
+                   -> Result<u64> {
+        let ops = ops.iter().map(|s| Op::parse(s)).collect::<Result<Vec<_>>>()?;
 
-                    for x in _x.iter() {
-                        if &vstr == x {
-                            res = true;
-                        }
-                        else {
-                            a.push(x.clone())
-                        }
-                    }
-
-                    if !a.is_empty() {
-                        data.insert(key_s, cv::Value::Array(a));
-                    }
-
-                    return res;
-                },
-
-                _ => (),
-            };
-
-            false
-        }
-
-        let mut cnt = 0;
-        let ref mut data = self.data;
-
-        //println!("before -- {:?}", data);
-
-        for op in ops.iter() {
-            if op.starts_with("+") {
-                if add(data, "tag", &op[1..], allow_new) { cnt += 1 }
-            }
-            else if op.starts_with("-") {
-                if del(data, "tag", &op[1..]) { cnt += 1 }
-            }
-            else if op.contains("+") {
-                let p = op.find("+").unwrap();
-                if add(data, &op[..p], &op[p+1..], allow_new) { cnt += 1 }
-            }
-            else if op.contains("-") {
-                let p = op.find("-").unwrap();
-                if del(data, &op[..p], &op[p+1..]) { cnt += 1 }
-            }
-            else if op.contains("=") {
-                let p = op.find("=").unwrap();
-                if set(data, &op[..p], &op[p+1..], allow_new) { cnt += 1 }
-            }
-        }
+        self.apply(schema, &ops, allow_new)
+    }
 
-        //println!("after -- {:?}", data);
+    // all or nothing
+    pub fn apply(&mut self, schema: &Schema, ops: &[Op], allow_new: bool)
+                 -> Result<u64> {
+        let mut data = self.data.clone();
+        let res = op::apply_all(&mut data, schema, ops, allow_new)?;
 
-        cnt
+        self.data = data;
+        Ok(res)
     }
 
     fn get_meta_path(&self) -> PathBuf {
@@ -225,54 +104,73 @@ impl Anno {
         self.data.get(key.into())
     }
 
-    fn parse_meta(&mut self) -> Result<()> {
+    fn read_meta(&self) -> Result<Meta> {
+        let content = fs::read_to_string(self.get_meta_path())?;
+
+        match meta::parse(&content) {
+            Ok(m) => Ok(m),
+            Err(Error::Simple(e)) => err(&format!("{}.meta, {}", self.rpath, e)),
+            Err(e) => Err(e),
+        }
+    }
+
+    // return version of .meta, see meta.rs
+    fn parse_meta(&mut self) -> Result<u32> {
+        let m = self.read_meta()?;
+
+        self.pid = m.pid;
+        self.fid = m.fid;
+        self.anno_hash = m.anno_hash;
+        self.content = m.content;
+
+        Ok(m.version)
+    }
+
+    pub fn parse_yaml(&mut self) -> Result<()> {
+        let mut file = File::open(self.get_yaml_path())?;
         let mut content = String::new();
-        let mut file = File::open(self.get_meta_path())?;
         file.read_to_string(&mut content)?;
 
-        // 1 -> _pid, 2 -> _ref_oid, 3 -> ref_pid
-        let mut t: i32 = 0;
+        if merge::has_conflict(&content) {
+            return err(&format!("unresolved conflict in {}, use nep-resolve",
+                                self.rpath))
+        }
 
-        // cbor
-        // { "_": [[pids], [ref_oid], [ref_pid]] }
+        self.parse_yaml_(&content)?;
 
-        for l in content.lines() {
-            if l.starts_with("# pid") {
-                t = 1;
-            }
-            else if l.starts_with("# ref_oid") {
-                t = 2;
-            }
-            else if l.starts_with("# anno_hash") {
-                t = 3;
-            }
-            else {
-                if l.len() == 52 { // ceiling(256 / 5)
-                    let id = util::zbase32_to_id(&l);
-
-                    if t == 1 {
-                        self.pid.push(id);
-                    }
-                    else if t == 2 {
-                        self.fid = id;
-                    }
-                    else if t == 3 {
-                        self.anno_hash = id;
-                    }
-                }
-            }
+        if self.is_removed() {
+            return err(&format!("reserved key {} in {}", REMOVED, self.rpath))
         }
 
         Ok(())
     }
 
+    // tombstone, the chain end here
+    pub fn is_removed(&self) -> bool {
+        self.data.contains_key(REMOVED)
+    }
 
-    pub fn parse_yaml(&mut self) -> Result<()> {
-        let mut file = File::open(self.get_yaml_path())?;
-        let mut content = String::new();
-        file.read_to_string(&mut content)?;
+    // yaml written by user, a map of text key, empty for none
+    pub fn parse_data(content: &str) -> Result<BTreeMap<String, cv::Value>> {
+        let mut res = BTreeMap::new();
+
+        // comment only, yaml parser take it as error
+        if content.lines().all(|l| l.trim().is_empty() || l.trim().starts_with('#')) {
+            return Ok(res)
+        }
+
+        match serde_yaml::from_str(content)? {
+            cv::Value::Null => (),
+            cv::Value::Map(m) => for (k, v) in m {
+                match k {
+                    cv::Value::Text(k) => { res.insert(k, v); },
+                    k => return err(&format!("key {:?} is not text", k)),
+                }
+            },
+            _ => return err("should be a map of key: value"),
+        }
 
-        self.parse_yaml_(&content)
+        Ok(res)
     }
 
     fn parse_yaml_(&mut self, content: &str) -> Result<()> {
@@ -349,6 +247,7 @@ impl Anno {
             pid: vec![],
             anno_hash: [0;32],
             fid: [0;32],
+            content: None,
 
             data: BTreeMap::new(),
             mdir: mdir.into(),
@@ -358,8 +257,9 @@ impl Anno {
         // if exist yaml & meta, then load
         if res.get_yaml_path().is_file() && res.get_meta_path().is_file() {
             debug!("new: load yaml & meta");
-            res.parse_meta()?;
+            let version = res.parse_meta()?;
             res.parse_yaml()?;
+            if version == 0 { res.upgrade_hash(); }
 
             if !load_only {
                 res.sync()?;
@@ -388,45 +288,161 @@ impl Anno {
         Ok(res)
     }
 
-    // save file
-    pub fn save(&mut self) -> Result<()> {
-        let file_meta = fs::metadata(self.get_file_path())?;
-        let ft = FileTime::from_last_modification_time(&file_meta);
+    // as new, from cache entry of unchanged file & sidecars
+    pub fn from_cache(mdir: &str, rpath: &str, e: &cache::Entry) -> Anno {
+        Anno {
+            pid: e.pid.clone(),
+            anno_hash: e.anno_hash,
+            fid: e.fid,
+            content: e.content,
 
-        // save meta
-        {
-            let mut meta = File::create(self.get_meta_path())?;
+            data: e.data.clone(),
+            mdir: mdir.into(),
+            rpath: rpath.into(),
+        }
+    }
 
-            //println!("meta = {:?}", meta);
+    pub fn to_cache(&self, stamps: [cache::Stamp; 3]) -> cache::Entry {
+        cache::Entry {
+            stamps,
+            pid: self.pid.clone(),
+            fid: self.fid,
+            anno_hash: self.anno_hash,
+            content: self.content,
+            data: self.data.clone(),
+        }
+    }
 
-            meta.write_fmt(format_args!("# pid\n"))?;
-            for i in self.pid.clone() {
-                meta.write_fmt(format_args!("{}\n", util::to_zbase32(&i)))?;
-            }
+    // load yaml & meta only, file may not exist
+    pub fn load(mdir: &str, rpath: &str) -> Result<Anno> {
+        let mut res = Anno {
+            pid: vec![],
+            anno_hash: [0;32],
+            fid: [0;32],
+            content: None,
+
+            data: BTreeMap::new(),
+            mdir: mdir.into(),
+            rpath: rpath.into(),
+        };
 
-            meta.write_fmt(format_args!("\n# ref_oid\n{}\n",
-                                        util::to_zbase32(&self.fid)))?;
+        let version = res.parse_meta()?;
+        res.parse_yaml()?;
+        if version == 0 { res.upgrade_hash(); }
 
-            meta.write_fmt(format_args!("\n# anno_hash\n{}\n",
-                                        util::to_zbase32(&self.anno_hash)))?;
+        Ok(res)
+    }
+
+    // stop tracking, file is kept
+    pub fn forget(self) -> Result<()> {
+        fs::remove_file(self.get_meta_path())?;
+        fs::remove_file(self.get_yaml_path())?;
+        Ok(())
+    }
+
+    // move sidecars along with file already at rpath, name follow it
+    pub fn rename(&mut self, rpath: &str) -> Result<()> {
+        let (meta, yaml) = (self.get_meta_path(), self.get_yaml_path());
+
+        self.rpath = rpath.to_string();
+        self.data.insert("name".into(), cv::Value::Text(rpath.into()));
+
+        if let Some(dir) = self.get_meta_path().parent() {
+            fs::create_dir_all(dir)?;
         }
 
-        //println!("ft = {:?}", ft);
-        filetime::set_file_times(self.get_meta_path(), ft, ft)?;
+        fs::rename(meta, self.get_meta_path())?;
+        fs::rename(yaml, self.get_yaml_path())?;
+
+        // uncommitted change, or other content at rpath of a missing one,
+        // clear fid so it stay MFile after save
+        let file_meta = fs::metadata(self.get_file_path())?;
+        let same = match self.content {
+            Some(c) => {
+                let now = self.get_content(&file_meta)?;
+                now.hash == c.hash && now.size == c.size
+            },
+            None => fs::metadata(self.get_meta_path())?.modified()? == file_meta.modified()?,
+        };
 
-        //let v: cv::Value = serde_yaml::from_str(&content).unwrap();
-        //self.data = v.as_object().unwrap().clone();
+        if !same { self.fid = [0; 32]; }
 
-        // save yaml
-        {
-            let mut yaml = File::create(self.get_yaml_path())?;
+        self.save()
+    }
+
+    // point to a committed version, e.g. after checkout
+    pub fn reset(&mut self, aid: &Id, from: &Anno) -> Result<()> {
+        self.pid = vec![*aid];
+        self.fid = from.fid;
+        self.data = from.data.clone();
+        self.anno_hash = self.get_hash();
+
+        // restore a removed one, need commit to revive the chain
+        if self.data.remove(REMOVED).is_some() {
+            self.anno_hash = [0; 32];
+        }
+
+        self.save()
+    }
+
+    // save meta, with yaml replaced by merge result contain marker
+    pub fn save_conflict(&mut self, yaml: &str) -> Result<()> {
+        self.save()?;
+
+        let ft = FileTime::from_last_modification_time(
+            &fs::metadata(self.get_yaml_path())?);
+
+        util::write_atomic(&self.get_yaml_path(), yaml.as_bytes())?;
+        filetime::set_file_times(self.get_yaml_path(), ft, ft)?;
+
+        Ok(())
+    }
+
+    // sha256 of file, again only when changed since last save
+    fn get_content(&self, file_meta: &fs::Metadata) -> Result<meta::Content> {
+        let ft = FileTime::from_last_modification_time(file_meta);
+        let size = file_meta.len();
+        let mtime = ft.unix_seconds() * 1_000_000_000 + ft.nanoseconds() as i64;
+
+        match self.content {
+            Some(c) if c.size == size && c.mtime == mtime => Ok(c),
+            _ => {
+                let path = self.get_file_path();
+                let hash = util::calc_id(&path.to_string_lossy())?;
+                Ok(meta::Content { hash, size, mtime })
+            },
+        }
+    }
 
-            //println!("yaml = {:?}", yaml);
+    fn write_meta(&mut self, file_meta: &fs::Metadata) -> Result<()> {
+        self.content = Some(self.get_content(file_meta)?);
 
-            let y = serde_yaml::to_string(&self.data).unwrap();
-            yaml.write_all(y.as_bytes())?;
+        let meta = Meta { version: meta::VERSION, pid: self.pid.clone(), fid: self.fid,
+                          anno_hash: self.anno_hash, content: self.content }.gen()?;
+
+        // file in sub directory
+        if let Some(dir) = self.get_meta_path().parent() {
+            fs::create_dir_all(dir)?;
         }
 
+        util::write_atomic(&self.get_meta_path(), meta.as_bytes())?;
+
+        Ok(())
+    }
+
+    // save file
+    pub fn save(&mut self) -> Result<()> {
+        let file_meta = fs::metadata(self.get_file_path())?;
+        let ft = FileTime::from_last_modification_time(&file_meta);
+
+        // save meta
+        self.write_meta(&file_meta)?;
+        filetime::set_file_times(self.get_meta_path(), ft, ft)?;
+
+        // save yaml
+        let yaml = serde_yaml::to_string(&self.data)?;
+
+        util::write_atomic(&self.get_yaml_path(), yaml.as_bytes())?;
         filetime::set_file_times(self.get_yaml_path(), ft, ft)?;
 
         // to avoid time round error
@@ -435,6 +451,33 @@ impl Anno {
         Ok(())
     }
 
+    // rewrite legacy .meta as current version, yaml untouched, false if
+    // already current. file should be unchanged since last save, known by
+    // equal mtime only, its content hash is then trusted as committed one
+    pub fn upgrade_meta(&mut self) -> Result<bool> {
+        // nothing changed until checked
+        if self.read_meta()?.version == meta::VERSION { return Ok(false) }
+
+        // content unknown
+        if !self.get_file_path().is_file() {
+            return err("file missing, nep-mv or nep-rm it first")
+        }
+
+        let file_meta = fs::metadata(self.get_file_path())?;
+        let ft = FileTime::from_last_modification_time(
+            &fs::metadata(self.get_meta_path())?);
+
+        if FileTime::from_last_modification_time(&file_meta) != ft {
+            return err("file changed since last save, commit it first")
+        }
+
+        if self.parse_meta()? == 0 { self.upgrade_hash(); }
+
+        self.write_meta(&file_meta)?;
+        filetime::set_file_times(self.get_meta_path(), ft, ft)?;
+
+        Ok(true)
+    }
 
     // decode from commit, not cbor
     pub fn decode(parents: &[Id], ref_oid: &Id, yaml: &str,
@@ -444,6 +487,7 @@ impl Anno {
             pid,
             fid: ref_oid.clone(),
             anno_hash: [0;32],
+            content: None,
             data: BTreeMap::new(),
             mdir: ".".to_string(),
             rpath: "".to_string(),
@@ -471,13 +515,27 @@ impl Anno {
         Ok(res)
     }
 
+    // of canonical cbor, see canon.rs
     pub fn get_hash(&self) -> Id {
+        canon::hash(&self.data)
+    }
+
+    // before canonical hash, depend on serde_yaml output
+    fn get_hash_legacy(&self) -> Id {
         let yaml = serde_yaml::to_vec(&self.data).unwrap();
         util::calc_id_buf(&yaml)
     }
 
+    // legacy hash of unchanged data to canonical one, so not MMeta
+    fn upgrade_hash(&mut self) {
+        if self.anno_hash == self.get_hash_legacy() {
+            self.anno_hash = self.get_hash();
+        }
+    }
+
+    // canonical, as commit message
     pub fn gen_yaml(&self) -> Result<String> {
-        Ok(serde_yaml::to_string(&self.data)?)
+        Ok("---\n".to_string() + &canon::to_yaml(&self.data)?)
     }
 
     // update yaml & meta, return changed status
@@ -551,6 +609,14 @@ impl Anno {
         Ok(())
     }
 
+    // in ms, as recorded by update_meta
+    pub fn get_mtime(&self) -> Option<u64> {
+        match self.data_get("mtime") {
+            Some(&cv::Value::Integer(t)) if t >= 0 => Some(t as u64),
+            _ => None
+        }
+    }
+
     pub fn get_name(&self) -> Option<String> {
         match self.data_get("name") {
             Some(&cv::Value::Text(ref v)) => Some(v.clone()),
@@ -0,0 +1,287 @@
+// binary cache of loaded anno, like git index, so unchanged one is not
+// parsed again
+//
+//   "NEPI" | u32 version | u32 count | entry... | sha256 of all before
+//
+//   entry: u16 len | rpath | stamp of file, .meta, .yaml
+//          | u8 n | pid * n | fid | anno_hash | content | u32 len | data in cbor
+//   content: u8 0, or 1 | sha256 | u64 size | i64 mtime, as in .meta
+//   stamp: i64 mtime | u32 nsec | u64 size | u64 ino
+//
+// integer in big endian; an entry is used only when all 3 stamps match,
+// and none is as new as the cache itself (racy, may change in same tick).
+// cache is rebuilt when missing, invalid or of other version
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use serde_cbor::value as cv;
+
+use crate::error::*;
+use crate::util::{self, Id};
+use crate::proj::meta::Content;
+
+pub const INDEX: &str = ".index";
+
+const MAGIC: &[u8] = b"NEPI";
+// 2: anno_hash of canonical cbor, 3: content of file
+const VERSION: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    pub mtime: i64,
+    pub nsec: u32,
+    pub size: u64,
+    pub ino: u64,
+}
+
+impl Stamp {
+    pub fn of(path: &Path) -> io::Result<Stamp> {
+        let m = fs::metadata(path)?;
+
+        Ok(Stamp { mtime: m.mtime(), nsec: m.mtime_nsec() as u32,
+                   size: m.size(), ino: m.ino() })
+    }
+}
+
+// file, .meta, .yaml of rpath
+pub fn stamps(mdir: &str, rpath: &str) -> io::Result<[Stamp; 3]> {
+    let m = Path::new(mdir);
+
+    Ok([Stamp::of(&m.parent().unwrap().join(rpath))?,
+        Stamp::of(&m.join(rpath.to_string() + ".meta"))?,
+        Stamp::of(&m.join(rpath.to_string() + ".yaml"))?])
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub stamps: [Stamp; 3],
+
+    pub pid: Vec<Id>,
+    pub fid: Id,
+    pub anno_hash: Id,
+    pub content: Option<Content>,
+    pub data: BTreeMap<String, cv::Value>,
+}
+
+#[derive(Debug)]
+pub struct Cache {
+    path: PathBuf,
+    entries: BTreeMap<String, Entry>,
+
+    // mtime of cache file
+    written: (i64, u32),
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::IO(io::Error::new(io::ErrorKind::InvalidData, msg.to_string()))
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < n { return Err(invalid("truncated cache")) }
+
+        let (res, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(res)
+    }
+
+    fn u8(&mut self) -> Result<u8> { Ok(self.take(1)?[0]) }
+
+    fn u16(&mut self) -> Result<u16> {
+        let mut b = [0; 2];
+        b.copy_from_slice(self.take(2)?);
+        Ok(u16::from_be_bytes(b))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(b))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(b))
+    }
+
+    fn id(&mut self) -> Result<Id> {
+        Ok(util::to_id(self.take(32)?))
+    }
+
+    fn stamp(&mut self) -> Result<Stamp> {
+        Ok(Stamp { mtime: self.u64()? as i64, nsec: self.u32()?,
+                   size: self.u64()?, ino: self.u64()? })
+    }
+}
+
+fn put_stamp(buf: &mut Vec<u8>, s: &Stamp) {
+    buf.extend_from_slice(&s.mtime.to_be_bytes());
+    buf.extend_from_slice(&s.nsec.to_be_bytes());
+    buf.extend_from_slice(&s.size.to_be_bytes());
+    buf.extend_from_slice(&s.ino.to_be_bytes());
+}
+
+pub fn encode(entries: &BTreeMap<String, Entry>) -> Result<Vec<u8>> {
+    let mut buf = MAGIC.to_vec();
+    buf.extend_from_slice(&VERSION.to_be_bytes());
+    buf.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+    for (rpath, e) in entries.iter() {
+        if rpath.len() > u16::MAX as usize || e.pid.len() > u8::MAX as usize {
+            return Err(invalid(&format!("can not cache {}", rpath)))
+        }
+
+        buf.extend_from_slice(&(rpath.len() as u16).to_be_bytes());
+        buf.extend_from_slice(rpath.as_bytes());
+
+        for s in e.stamps.iter() { put_stamp(&mut buf, s); }
+
+        buf.push(e.pid.len() as u8);
+        for p in e.pid.iter() { buf.extend_from_slice(p); }
+
+        buf.extend_from_slice(&e.fid);
+        buf.extend_from_slice(&e.anno_hash);
+
+        match e.content {
+            Some(c) => {
+                buf.push(1);
+                buf.extend_from_slice(&c.hash);
+                buf.extend_from_slice(&c.size.to_be_bytes());
+                buf.extend_from_slice(&c.mtime.to_be_bytes());
+            },
+            None => buf.push(0),
+        }
+
+        let data = serde_cbor::to_vec(&e.data)?;
+        buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&data);
+    }
+
+    let sum = util::calc_id_buf(&buf);
+    buf.extend_from_slice(&sum);
+
+    Ok(buf)
+}
+
+pub fn decode(buf: &[u8]) -> Result<BTreeMap<String, Entry>> {
+    if buf.len() < MAGIC.len() + 8 + 32 { return Err(invalid("truncated cache")) }
+
+    let (body, sum) = buf.split_at(buf.len() - 32);
+    if util::calc_id_buf(body) != sum { return Err(invalid("cache checksum mismatch")) }
+
+    let mut r = Reader { buf: body };
+
+    if r.take(MAGIC.len())? != MAGIC { return Err(invalid("not a cache")) }
+
+    let version = r.u32()?;
+    if version != VERSION {
+        return Err(invalid(&format!("cache version {}, expect {}", version, VERSION)))
+    }
+
+    let mut res = BTreeMap::new();
+
+    for _ in 0..r.u32()? {
+        let len = r.u16()? as usize;
+        let rpath = String::from_utf8(r.take(len)?.to_vec())
+            .map_err(|_| invalid("invalid path in cache"))?;
+
+        let stamps = [r.stamp()?, r.stamp()?, r.stamp()?];
+
+        let n = r.u8()?;
+        let pid = (0..n).map(|_| r.id()).collect::<Result<Vec<_>>>()?;
+
+        let fid = r.id()?;
+        let anno_hash = r.id()?;
+
+        let content = match r.u8()? {
+            0 => None,
+            1 => Some(Content { hash: r.id()?, size: r.u64()?, mtime: r.u64()? as i64 }),
+            _ => return Err(invalid("invalid content in cache")),
+        };
+
+        let len = r.u32()? as usize;
+        let data = serde_cbor::from_slice(r.take(len)?)?;
+
+        res.insert(rpath, Entry { stamps, pid, fid, anno_hash, content, data });
+    }
+
+    if !r.buf.is_empty() { return Err(invalid("trailing data in cache")) }
+
+    Ok(res)
+}
+
+impl Cache {
+    // never fail, empty if no usable cache
+    pub fn load(mdir: &str) -> Cache {
+        let path = Path::new(mdir).join(INDEX);
+
+        let res = Stamp::of(&path).map_err(Error::from)
+            .and_then(|s| Ok((s, fs::read(&path)?)))
+            .and_then(|(s, buf)| Ok(((s.mtime, s.nsec), decode(&buf)?)));
+
+        match res {
+            Ok((written, entries)) => Cache { path, entries, written },
+            Err(e) => {
+                debug!("cache: not used, {}", e);
+                Cache { path, entries: BTreeMap::new(), written: (0, 0) }
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, rpath: &str, stamps: &[Stamp; 3]) -> Option<&Entry> {
+        let racy = stamps.iter().any(|s| (s.mtime, s.nsec) >= self.written);
+
+        self.entries.get(rpath)
+            .filter(|e| !racy && &e.stamps == stamps)
+    }
+
+    pub fn save(&self, entries: &BTreeMap<String, Entry>) -> Result<()> {
+        util::write_atomic(&self.path, &encode(entries)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let s = Stamp { mtime: 1600000000, nsec: 5, size: 42, ino: 7 };
+        let mut data = BTreeMap::new();
+        data.insert("name".to_string(), cv::Value::Text("a b.txt".into()));
+        data.insert("size".to_string(), cv::Value::Integer(42));
+
+        let mut entries = BTreeMap::new();
+        entries.insert("a b.txt".to_string(),
+                       Entry { stamps: [s, s, s], pid: vec![[1; 32], [2; 32]],
+                               fid: [3; 32], anno_hash: [4; 32],
+                               content: Some(Content { hash: [5; 32], size: 42,
+                                                       mtime: -1 }),
+                               data });
+
+        let mut buf = encode(&entries).unwrap();
+        assert_eq!(decode(&buf).unwrap(), entries);
+
+        buf[10] ^= 1;
+        assert!(decode(&buf).is_err());
+    }
+}
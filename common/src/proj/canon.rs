@@ -0,0 +1,295 @@
+// canonical form of anno data, not depend on serde_yaml/serde_cbor version
+//
+// cbor is deterministic encoding of RFC 8949 4.2.1: shortest integer and
+// length, definite length only, map key sorted by its encoded bytes, float
+// as the shortest of f16/f32/f64 keep value. anno_hash is sha256 of it
+//
+// yaml is for commit message: one "key: value" line for each key in order,
+// list of scalar as block, nested one in flow style; text is plain when
+// safe, else double quoted. float always has "." or "e"
+
+use std::collections::BTreeMap;
+
+use serde_cbor::value as cv;
+
+use crate::error::*;
+use crate::util::{self, Id};
+
+fn head(major: u8, n: u64, out: &mut Vec<u8>) {
+    let m = major << 5;
+
+    if n < 24 {
+        out.push(m | n as u8);
+    }
+    else if n <= u8::MAX as u64 {
+        out.push(m | 24);
+        out.push(n as u8);
+    }
+    else if n <= u16::MAX as u64 {
+        out.push(m | 25);
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    }
+    else if n <= u32::MAX as u64 {
+        out.push(m | 26);
+        out.extend_from_slice(&(n as u32).to_be_bytes());
+    }
+    else {
+        out.push(m | 27);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+// half precision bits, if exact
+fn to_f16(x: f64) -> Option<u16> {
+    if x.is_nan() { return Some(0x7e00) }
+
+    let sign = if x.is_sign_negative() { 0x8000 } else { 0 };
+    let a = x.abs();
+
+    if a == 0.0 { return Some(sign) }
+    if a.is_infinite() { return Some(sign | 0x7c00) }
+
+    let bits = a.to_bits();
+    let exp = ((bits >> 52) & 0x7ff) as i32 - 1023;
+    let mant = bits & ((1 << 52) - 1);
+
+    if exp > 15 { return None }
+
+    if exp >= -14 {
+        // normal, 10 bits mantissa
+        if mant & ((1 << 42) - 1) != 0 { return None }
+        return Some(sign | (((exp + 15) as u16) << 10) | (mant >> 42) as u16)
+    }
+
+    // subnormal, n * 2^-24
+    let n = a * (1u64 << 24) as f64;
+    if n.fract() == 0.0 && n < 1024.0 { Some(sign | n as u16) } else { None }
+}
+
+fn float(x: f64, out: &mut Vec<u8>) {
+    if let Some(h) = to_f16(x) {
+        out.push(0xf9);
+        out.extend_from_slice(&h.to_be_bytes());
+    }
+    else if (x as f32) as f64 == x {
+        out.push(0xfa);
+        out.extend_from_slice(&(x as f32).to_bits().to_be_bytes());
+    }
+    else {
+        out.push(0xfb);
+        out.extend_from_slice(&x.to_bits().to_be_bytes());
+    }
+}
+
+fn integer(i: i128, out: &mut Vec<u8>) {
+    if i >= 0 && i <= u64::MAX as i128 {
+        head(0, i as u64, out);
+    }
+    else if i < 0 && -1 - i <= u64::MAX as i128 {
+        head(1, (-1 - i) as u64, out);
+    }
+    else {
+        // bignum, tag 2 or 3
+        let (tag, n) = if i >= 0 { (2, i as u128) } else { (3, (-1 - i) as u128) };
+        let bytes = n.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+
+        head(6, tag, out);
+        head(2, (16 - skip) as u64, out);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+}
+
+fn encode(v: &cv::Value, out: &mut Vec<u8>) {
+    match v {
+        cv::Value::Null => out.push(0xf6),
+        cv::Value::Bool(false) => out.push(0xf4),
+        cv::Value::Bool(true) => out.push(0xf5),
+        cv::Value::Integer(i) => integer(*i, out),
+        cv::Value::Float(f) => float(*f, out),
+        cv::Value::Bytes(b) => {
+            head(2, b.len() as u64, out);
+            out.extend_from_slice(b);
+        },
+        cv::Value::Text(s) => {
+            head(3, s.len() as u64, out);
+            out.extend_from_slice(s.as_bytes());
+        },
+        cv::Value::Array(a) => {
+            head(4, a.len() as u64, out);
+            for x in a.iter() { encode(x, out); }
+        },
+        cv::Value::Map(m) => {
+            let mut kv: Vec<(Vec<u8>, Vec<u8>)> = m.iter().map(|(k, v)| {
+                let (mut ek, mut ev) = (vec![], vec![]);
+                encode(k, &mut ek);
+                encode(v, &mut ev);
+                (ek, ev)
+            }).collect();
+
+            kv.sort();
+
+            head(5, kv.len() as u64, out);
+            for (k, v) in kv.into_iter() {
+                out.extend_from_slice(&k);
+                out.extend_from_slice(&v);
+            }
+        },
+        cv::Value::Tag(t, x) => {
+            head(6, *t, out);
+            encode(x, out);
+        },
+        // never constructed, as undefined
+        _ => out.push(0xf7),
+    }
+}
+
+pub fn to_cbor(data: &BTreeMap<String, cv::Value>) -> Vec<u8> {
+    let map = cv::Value::Map(data.iter()
+        .map(|(k, v)| (cv::Value::Text(k.clone()), v.clone())).collect());
+
+    let mut res = vec![];
+    encode(&map, &mut res);
+    res
+}
+
+pub fn hash(data: &BTreeMap<String, cv::Value>) -> Id {
+    util::calc_id_buf(&to_cbor(data))
+}
+
+// would be read as other type, or need quote
+const RESERVED: &[&str] = &["true", "false", "null", "yes", "no", "on", "off",
+                            "y", "n", "~"];
+
+fn text(s: &str) -> String {
+    let plain =
+        s.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') &&
+        s.chars().all(|c| c.is_ascii_alphanumeric() || "_./-".contains(c)) &&
+        !RESERVED.contains(&s.to_ascii_lowercase().as_str());
+
+    if plain { return s.to_string() }
+
+    let mut res = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => res += "\\\"",
+            '\\' => res += "\\\\",
+            '\n' => res += "\\n",
+            '\t' => res += "\\t",
+            '\r' => res += "\\r",
+            c if (c as u32) < 0x20 || c as u32 == 0x7f => {
+                res += &format!("\\u{:04x}", c as u32)
+            },
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+
+    res
+}
+
+fn scalar(v: &cv::Value) -> Result<String> {
+    Ok(match v {
+        cv::Value::Null => "null".to_string(),
+        cv::Value::Bool(b) => b.to_string(),
+        cv::Value::Integer(i) => i.to_string(),
+        cv::Value::Float(f) if f.is_nan() => ".nan".to_string(),
+        cv::Value::Float(f) if f.is_infinite() => {
+            if *f > 0.0 { ".inf".to_string() } else { "-.inf".to_string() }
+        },
+        cv::Value::Float(f) => {
+            // shortest one read back the same
+            let s = format!("{:?}", f);
+            if s.contains(['.', 'e']) { s } else { s + ".0" }
+        },
+        cv::Value::Text(s) => text(s),
+        cv::Value::Tag(_, x) => scalar(x)?,
+        cv::Value::Array(a) => {
+            let a = a.iter().map(scalar).collect::<Result<Vec<_>>>()?;
+            format!("[{}]", a.join(", "))
+        },
+        cv::Value::Map(m) => {
+            let m = m.iter().map(|(k, v)| Ok(format!("{}: {}", scalar(k)?, scalar(v)?)))
+                .collect::<Result<Vec<_>>>()?;
+            format!("{{{}}}", m.join(", "))
+        },
+        _ => return err("binary value can not be yaml"),
+    })
+}
+
+pub fn to_yaml(data: &BTreeMap<String, cv::Value>) -> Result<String> {
+    let mut res = String::new();
+
+    for (k, v) in data.iter() {
+        match v {
+            cv::Value::Array(a) if !a.is_empty() => {
+                res += &format!("{}:\n", text(k));
+                for x in a.iter() {
+                    res += &format!("  - {}\n", scalar(x)?);
+                }
+            },
+            v => res += &format!("{}: {}\n", text(k), scalar(v)?),
+        }
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn enc(v: cv::Value) -> String {
+        let mut out = vec![];
+        encode(&v, &mut out);
+        hex::encode(out)
+    }
+
+    #[test]
+    fn test_cbor() {
+        // RFC 8949 appendix A
+        assert_eq!(enc(cv::Value::Integer(24)), "1818");
+        assert_eq!(enc(cv::Value::Integer(1000000)), "1a000f4240");
+        assert_eq!(enc(cv::Value::Integer(-1000)), "3903e7");
+        assert_eq!(enc(cv::Value::Integer(18446744073709551616)),
+                   "c249010000000000000000");
+        assert_eq!(enc(cv::Value::Float(0.0)), "f90000");
+        assert_eq!(enc(cv::Value::Float(-0.0)), "f98000");
+        assert_eq!(enc(cv::Value::Float(1.5)), "f93e00");
+        assert_eq!(enc(cv::Value::Float(65504.0)), "f97bff");
+        assert_eq!(enc(cv::Value::Float(100000.0)), "fa47c35000");
+        assert_eq!(enc(cv::Value::Float(1.1)), "fb3ff199999999999a");
+        assert_eq!(enc(cv::Value::Float(5.960464477539063e-8)), "f90001");
+        assert_eq!(enc(cv::Value::Float(f64::NEG_INFINITY)), "f9fc00");
+
+        let mut data = BTreeMap::new();
+        data.insert("b".to_string(), cv::Value::Integer(1));
+        data.insert("aa".to_string(), cv::Value::Integer(2));
+        data.insert("a".to_string(), cv::Value::Integer(3));
+        assert_eq!(hex::encode(to_cbor(&data)), "a361610361620162616102");
+    }
+
+    #[test]
+    fn test_yaml() {
+        let mut data = BTreeMap::new();
+        data.insert("name".to_string(), cv::Value::Text("dir/a b.txt".into()));
+        data.insert("rate".to_string(), cv::Value::Float(3.0));
+        data.insert("size".to_string(), cv::Value::Integer(2));
+        data.insert("tag".to_string(), cv::Value::Array(vec![
+            cv::Value::Text("x".into()), cv::Value::Text("yes".into()),
+            cv::Value::Text("1".into())]));
+        data.insert("note".to_string(), cv::Value::Text("a: \"b\"\n".into()));
+        data.insert("level".to_string(), cv::Value::Array(vec![]));
+
+        let yaml = to_yaml(&data).unwrap();
+        assert_eq!(yaml, concat!("level: []\n",
+                                 "name: \"dir/a b.txt\"\n",
+                                 "note: \"a: \\\"b\\\"\\n\"\n",
+                                 "rate: 3.0\n",
+                                 "size: 2\n",
+                                 "tag:\n  - x\n  - \"yes\"\n  - \"1\"\n"));
+
+        let back: BTreeMap<String, cv::Value> = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(back, data);
+    }
+}
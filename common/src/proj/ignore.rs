@@ -0,0 +1,204 @@
+// gitignore style .nepignore, rules of a directory apply to it and below
+//
+//   # comment
+//   *.tmp        match name at any depth
+//   /build       anchored to directory of the .nepignore
+//   cache/       directory only
+//   !keep.tmp    negate, last match win
+//
+// .manifest is never added, VCS directories are ignored by default
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+
+use crate::error::*;
+use crate::proj::MANIFEST;
+
+pub const NEPIGNORE: &str = ".nepignore";
+
+const DEFAULT: &[&str] = &[".git/", ".hg/", ".svn/", ".bzr/", "_darcs/", ".pijul/"];
+
+const OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+#[derive(Debug)]
+struct Rule {
+    // rpath of directory contain the rule, "" for project root
+    base: String,
+    pat: Pattern,
+    neg: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+#[derive(Debug)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+fn join(base: &str, name: &str) -> String {
+    if base.is_empty() { name.to_string() } else { format!("{}/{}", base, name) }
+}
+
+impl Ignore {
+    pub fn new() -> Ignore {
+        let mut res = Ignore { rules: vec![] };
+        res.parse("", &DEFAULT.join("\n"));
+        res
+    }
+
+    // invalid pattern is skipped, like git
+    pub fn parse(&mut self, base: &str, content: &str) {
+        for ln in content.lines() {
+            let ln = ln.trim_end();
+            if ln.is_empty() || ln.starts_with('#') { continue }
+
+            let (neg, ln) = match ln.strip_prefix('!') {
+                Some(x) => (true, x),
+                None => (false, ln.strip_prefix('\\').unwrap_or(ln)),
+            };
+
+            let (dir_only, ln) = match ln.strip_suffix('/') {
+                Some(x) => (true, x),
+                None => (false, ln),
+            };
+
+            let anchored = ln.contains('/');
+            let ln = ln.trim_start_matches('/');
+
+            if let Ok(pat) = Pattern::new(ln) {
+                self.rules.push(Rule { base: base.to_string(), pat, neg,
+                                       dir_only, anchored });
+            }
+        }
+    }
+
+    // .nepignore of rdir, if any
+    pub fn load(&mut self, root: &Path, rdir: &str) -> Result<()> {
+        match fs::read_to_string(root.join(rdir).join(NEPIGNORE)) {
+            Ok(c) => { self.parse(rdir, &c); Ok(()) },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // rules from root down to rdir
+    pub fn for_dir(root: &Path, rdir: &str) -> Result<Ignore> {
+        let mut res = Ignore::new();
+        res.load(root, "")?;
+
+        let mut cur = String::new();
+        for c in rdir.split('/').filter(|c| !c.is_empty()) {
+            cur = join(&cur, c);
+            res.load(root, &cur)?;
+        }
+
+        Ok(res)
+    }
+
+    pub fn is_ignored(&self, rpath: &str, is_dir: bool) -> bool {
+        if rpath == MANIFEST || rpath.starts_with(&format!("{}/", MANIFEST)) {
+            return true
+        }
+
+        let name = rpath.rsplit('/').next().unwrap_or(rpath);
+        let mut res = false;
+
+        for r in self.rules.iter() {
+            if r.dir_only && !is_dir { continue }
+
+            let rel = if r.base.is_empty() { rpath }
+                      else {
+                          match rpath.strip_prefix(&format!("{}/", r.base)) {
+                              Some(x) => x,
+                              None => continue,
+                          }
+                      };
+
+            let hit = if r.anchored { r.pat.matches_with(rel, OPTIONS) }
+                      else { r.pat.matches_with(name, OPTIONS) };
+
+            if hit { res = !r.neg; }
+        }
+
+        res
+    }
+
+    // files under rdir not ignored, as rpath, sorted; symlinked
+    // directory is not followed
+    pub fn walk(&mut self, root: &Path, rdir: &str) -> Result<Vec<String>> {
+        let mut res = vec![];
+        let mut remain = vec![rdir.to_string()];
+
+        while let Some(dir) = remain.pop() {
+            if dir != rdir { self.load(root, &dir)?; }
+
+            let mut entries: Vec<(String, PathBuf)> = fs::read_dir(root.join(&dir))?
+                .map(|e| e.map(|e| (e.file_name().to_string_lossy().to_string(),
+                                    e.path())))
+                .collect::<io::Result<_>>()?;
+            entries.sort();
+
+            let mut subdirs = vec![];
+
+            for (name, path) in entries.into_iter() {
+                let rpath = join(&dir, &name);
+                let ft = fs::symlink_metadata(&path)?.file_type();
+                let is_dir = ft.is_dir();
+
+                if self.is_ignored(&rpath, is_dir) { continue }
+
+                if is_dir { subdirs.push(rpath); }
+                else if path.is_file() { res.push(rpath); }
+            }
+
+            // depth first, in order
+            remain.extend(subdirs.into_iter().rev());
+        }
+
+        res.sort();
+        Ok(res)
+    }
+}
+
+impl Default for Ignore {
+    fn default() -> Ignore {
+        Ignore::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ignore() {
+        let mut ig = Ignore::new();
+        ig.parse("", "*.tmp\n!keep.tmp\n/build\ncache/\n# c\n");
+        ig.parse("sub", "/local\n");
+
+        assert!(ig.is_ignored(".manifest", true));
+        assert!(ig.is_ignored(".git", true));
+        assert!(!ig.is_ignored(".gitignore", false));
+
+        assert!(ig.is_ignored("a.tmp", false));
+        assert!(ig.is_ignored("x/y/a.tmp", false));
+        assert!(!ig.is_ignored("x/keep.tmp", false));
+
+        assert!(ig.is_ignored("build", true));
+        assert!(!ig.is_ignored("x/build", true));
+
+        assert!(ig.is_ignored("x/cache", true));
+        assert!(!ig.is_ignored("x/cache", false));
+
+        assert!(ig.is_ignored("sub/local", false));
+        assert!(!ig.is_ignored("local", false));
+        assert!(!ig.is_ignored("sub/x/local", false));
+    }
+}
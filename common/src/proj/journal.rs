@@ -0,0 +1,135 @@
+// journal of in-progress commit
+//
+// one line for each anno commit made but not yet in a changeset:
+// "<aid> <old fid> <old anno_hash> <old pid,...|-> <rpath>", all id in hex
+//
+// removed after changeset ref updated, so a left journal means the last
+// commit is interrupted, and can be resumed or rolled back
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::*;
+use crate::git;
+use crate::util::Id;
+
+pub const JOURNAL: &str = ".journal";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub aid: Id,
+
+    // state before commit, for rollback
+    pub fid: Id,
+    pub anno_hash: Id,
+    pub pid: Vec<Id>,
+
+    pub rpath: String,
+}
+
+impl Entry {
+    fn encode(&self) -> String {
+        let pid = if self.pid.is_empty() { "-".to_string() }
+                  else { self.pid.iter().map(hex::encode)
+                         .collect::<Vec<_>>().join(",") };
+
+        format!("{} {} {} {} {}\n",
+                hex::encode(self.aid), hex::encode(self.fid),
+                hex::encode(self.anno_hash), pid, self.rpath)
+    }
+
+    fn decode(ln: &str) -> Result<Entry> {
+        let f: Vec<&str> = ln.splitn(5, ' ').collect();
+
+        if f.len() != 5 {
+            return err(&format!("invalid journal line '{}'", ln))
+        }
+
+        let pid = if f[3] == "-" { vec![] }
+                  else { f[3].split(',').map(git::parse_hex)
+                         .collect::<Result<Vec<_>>>()? };
+
+        Ok(Entry {
+            aid: git::parse_hex(f[0])?,
+            fid: git::parse_hex(f[1])?,
+            anno_hash: git::parse_hex(f[2])?,
+            pid,
+            rpath: f[4].to_string(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub fn new(mdir: &str) -> Journal {
+        Journal { path: Path::new(mdir).join(JOURNAL) }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.is_file()
+    }
+
+    // entries of interrupted commit, empty if none
+    pub fn load(&self) -> Result<Vec<Entry>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let lines: Vec<&str> = content.lines().filter(|l| !l.is_empty()).collect();
+
+        // last line may be partial, if crash when append
+        lines.iter().enumerate()
+            .filter_map(|(i, l)| match Entry::decode(l) {
+                Err(_) if i + 1 == lines.len() => None,
+                x => Some(x),
+            })
+            .collect()
+    }
+
+    // durable before .meta point to the new commit
+    pub fn append(&self, entry: &Entry) -> Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true)
+            .open(&self.path)?;
+
+        file.write_all(entry.encode().as_bytes())?;
+        file.sync_all()?;
+
+        Ok(())
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_entry() {
+        let e = Entry { aid: [1; 32], fid: [2; 32], anno_hash: [3; 32],
+                        pid: vec![[4; 32], [5; 32]],
+                        rpath: "a dir/with space.txt".to_string() };
+
+        assert_eq!(Entry::decode(e.encode().trim_end()).unwrap(), e);
+
+        let e = Entry { pid: vec![], ..e };
+        assert_eq!(Entry::decode(e.encode().trim_end()).unwrap(), e);
+    }
+}
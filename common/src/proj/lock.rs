@@ -0,0 +1,58 @@
+// manifest lock, one writer of .meta/.yaml at a time
+//
+// flock on .lock, released by os when the holder exit, so never stale.
+// the file is kept, pid in it is only for message
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::Path;
+use std::process;
+
+use log::debug;
+
+use crate::error::*;
+
+pub const LOCK: &str = ".lock";
+
+// released when drop
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    fn holder(path: &Path) -> Option<u32> {
+        fs::read_to_string(path).ok()
+            .and_then(|s| s.trim().parse::<u32>().ok())
+    }
+
+    // wait until free when `wait`, or fail at once
+    pub fn acquire(mdir: &str, wait: bool) -> Result<Lock> {
+        let path = Path::new(mdir).join(LOCK);
+
+        let mut file = OpenOptions::new().write(true).create(true).truncate(false)
+            .open(&path)?;
+
+        if wait {
+            file.lock()?;
+        }
+        else {
+            match file.try_lock() {
+                Ok(()) => (),
+                Err(TryLockError::WouldBlock) => {
+                    let by = Self::holder(&path).map_or("another process".to_string(),
+                                                        |p| format!("pid {}", p));
+                    return err(&format!("manifest locked by {}, use --wait", by))
+                },
+                Err(TryLockError::Error(e)) => return Err(e.into()),
+            }
+        }
+
+        file.set_len(0)?;
+        writeln!(file, "{}", process::id())?;
+
+        debug!("lock: acquire {:?}", path);
+
+        Ok(Lock { _file: file })
+    }
+}
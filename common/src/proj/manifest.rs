@@ -2,10 +2,19 @@
 use crate::proj::*;
 use crate::error::*;
 
-use std::fs;
-use std::env;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
 
-use glob::glob;
+use glob::{glob, Pattern};
+use log::debug;
+use serde_cbor::value as cv;
+
+use crate::hashsplit;
+use crate::util;
+use crate::proj::ignore::Ignore;
+use crate::proj::cache::{self, Cache};
+use crate::proj::schema::Schema;
 
 //use crate::util::Id;
 
@@ -14,42 +23,97 @@ use std::collections::BTreeMap;
 #[derive(Debug)]
 pub struct Manifest {
     pub mdir: String,
-    pub anno_map: BTreeMap<String, anno::Anno>
+    pub anno_map: BTreeMap<String, anno::Anno>,
+
+    // sidecars remain, file is gone, e.g. moved
+    pub missing: BTreeMap<String, anno::Anno>,
+
+    // sidecars fail to load, e.g. invalid .meta, as (rpath, error)
+    pub failed: Vec<(String, String)>,
+
+    pub schema: Schema,
+
+    // hold until drop
+    _lock: lock::Lock,
 }
 
 impl Manifest {
-    // TODO: create lock
     pub fn new(mdir: &str) -> Result<Manifest> {
+        Self::open(mdir, false)
+    }
+
+    // manifest of project contain cwd
+    pub fn find(wait: bool) -> Result<Manifest> {
+        Self::open(&Project::find()?.mdir(), wait)
+    }
+
+    // lock manifest, wait for other nep to finish when `wait`
+    pub fn open(mdir: &str, wait: bool) -> Result<Manifest> {
+        if !Path::new(mdir).is_dir() {
+            return err(&format!("manifest {} not exist", mdir))
+        }
+
         let mut res = Manifest {
             mdir: mdir.to_string(),
-            anno_map: BTreeMap::new()
+            anno_map: BTreeMap::new(),
+            missing: BTreeMap::new(),
+            failed: vec![],
+            schema: Schema::load(mdir)?,
+            _lock: lock::Lock::acquire(mdir, wait)?,
         };
 
-        fs::create_dir_all(mdir)?;
+        // NOTE: path relative to mdir, without change cwd
+        let pattern = Path::new(&Pattern::escape(mdir)).join("**/*.meta");
+        let paths = glob(&pattern.to_string_lossy()).unwrap().
+            filter_map(|x| x.ok()).
+            filter_map(|x| x.strip_prefix(mdir).ok().map(|x| x.to_path_buf())).
+            collect::<Vec<_>>();
 
-        // NOTE: here to ensure path is relative to correct base path
-        let cwd = env::current_dir()?;
-        env::set_current_dir(&mdir)?;
-        let paths = glob(&"**/*.meta").unwrap().
-            filter_map(|x| x.ok()).collect::<Vec<_>>();
-        env::set_current_dir(&cwd)?;
+        let cache = Cache::load(mdir);
+        let mut entries = BTreeMap::new();
+        let mut dirty = false;
 
         for path in paths {
             match path.into_os_string().into_string() {
                 Ok(mut p) => {
                     let l = p.len();
                     p.truncate(l - 5);
+
+                    // unchanged since cached, skip parse & sync
+                    let stamps = cache::stamps(mdir, &p).ok();
+                    if let Some(e) = stamps.as_ref().and_then(|s| cache.get(&p, s)) {
+                        res.anno_map.insert(p.clone(), anno::Anno::from_cache(mdir, &p, e));
+                        entries.insert(p, e.clone());
+                        continue
+                    }
+
+                    dirty = true;
+
                     // println!("p = {}", p);
                     match anno::Anno::new(mdir, &p, false) {
                         Ok(anno) => {
                             //let g = anno.gen().unwrap();
                             //println!("{:?}", g);
                             //println!("{:?}", to_zbase32(&calc_id_buf(&g)));
+                            if let Ok(s) = cache::stamps(mdir, &p) {
+                                entries.insert(p.clone(), anno.to_cache(s));
+                            }
+
                             let name = p.to_string();
                             res.anno_map.insert(name, anno);
                         },
+                        Err(_) if !res.root().join(&p).exists() => {
+                            match anno::Anno::load(mdir, &p) {
+                                Ok(anno) => { res.missing.insert(p, anno); },
+                                Err(e) => {
+                                    eprintln!("Error load {}, {:?}", &p, e);
+                                    res.failed.push((p, e.to_string()));
+                                },
+                            }
+                        },
                         Err(e) => {
-                            println!("Error load {}, {:?}", &p, e)
+                            eprintln!("Error load {}, {:?}", &p, e);
+                            res.failed.push((p, e.to_string()));
                         }
                     }
                 },
@@ -57,6 +121,13 @@ impl Manifest {
             }
         }
 
+        // best effort, e.g. read only project
+        if dirty || entries.len() != cache.len() {
+            if let Err(e) = cache.save(&entries) {
+                debug!("cache: fail to save, {}", e);
+            }
+        }
+
         Ok(res)
     }
 
@@ -68,4 +139,100 @@ impl Manifest {
 
         Ok(name)
     }
+
+    pub fn root(&self) -> PathBuf {
+        Path::new(&self.mdir).parent().unwrap().to_path_buf()
+    }
+
+    // missing file whose content now at an untracked path, as (old, new)
+    pub fn moves(&self) -> Result<Vec<(String, String)>> {
+        // content hash of .meta, or fid of legacy one; unknown if neither
+        let mut left: Vec<(&String, &anno::Anno)> = self.missing.iter()
+            .filter(|(_, a)| a.content.is_some() || a.fid != [0; 32]).collect();
+
+        let mut res = vec![];
+        if left.is_empty() { return Ok(res) }
+
+        let root = self.root();
+        let untracked = Ignore::for_dir(&root, "")?.walk(&root, "")?.into_iter()
+            .filter(|r| !self.anno_map.contains_key(r) && !self.missing.contains_key(r));
+
+        for r in untracked {
+            let path = root.join(&r);
+            let size = fs::metadata(&path)?.len();
+
+            let same_size = |a: &anno::Anno| match (a.content, a.data_get("size")) {
+                (Some(c), _) => c.size == size,
+                (None, Some(cv::Value::Integer(s))) => *s == size as i128,
+                _ => true,
+            };
+
+            // hash only when size match
+            if !left.iter().any(|(_, a)| same_size(a)) { continue }
+
+            let hash = if left.iter().any(|(_, a)| a.content.is_some() && same_size(a)) {
+                Some(util::calc_id(&path.to_string_lossy())?)
+            } else { None };
+
+            let fid = if left.iter().any(|(_, a)| a.content.is_none() && same_size(a)) {
+                Some(hashsplit::calc_id(&mut BufReader::new(File::open(&path)?))?)
+            } else { None };
+
+            let found = left.iter().position(|(_, a)| match a.content {
+                Some(c) => Some(c.hash) == hash,
+                None => Some(a.fid) == fid,
+            });
+
+            if let Some(i) = found {
+                res.push((left.remove(i).0.clone(), r));
+                if left.is_empty() { break }
+            }
+        }
+
+        Ok(res)
+    }
+
+    // stop tracking, tracked or missing, file itself is kept
+    pub fn forget(&mut self, rpath: &str) -> Result<()> {
+        match self.anno_map.remove(rpath).or_else(|| self.missing.remove(rpath)) {
+            Some(a) => a.forget(),
+            None => err(&format!("{} not in manifest", rpath)),
+        }
+    }
+
+    // move tracked file, or sidecars of missing one to where file now is
+    pub fn mv(&mut self, old: &str, new: &str) -> Result<()> {
+        if self.anno_map.contains_key(new) || self.missing.contains_key(new) {
+            return err(&format!("{} already in manifest", new))
+        }
+
+        let root = self.root();
+        let path = root.join(new);
+
+        let mut anno = if let Some(a) = self.anno_map.remove(old) {
+            if path.exists() {
+                self.anno_map.insert(old.to_string(), a);
+                return err(&format!("{} exists", new))
+            }
+
+            if let Some(dir) = path.parent() { fs::create_dir_all(dir)?; }
+            fs::rename(root.join(old), &path)?;
+            a
+        }
+        else if self.missing.contains_key(old) {
+            if !path.is_file() {
+                return err(&format!("{} not found, move file there first", new))
+            }
+
+            self.missing.remove(old).unwrap()
+        }
+        else {
+            return err(&format!("{} not in manifest", old))
+        };
+
+        anno.rename(new)?;
+        self.anno_map.insert(new.to_string(), anno);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,248 @@
+// three-way merge of anno data
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_cbor::value as cv;
+
+use crate::error::*;
+
+type Data = BTreeMap<String, cv::Value>;
+
+// computed from file, follow side whose file is taken
+const COMPUTED: [&str; 3] = ["mtime", "size", "type"];
+
+// always merge as set, even if all side is scalar
+const SET_KEYS: [&str; 1] = ["tag"];
+
+const MARK_OURS: &str = "<<<<<<<";
+const MARK_SEP: &str = "=======";
+const MARK_THEIRS: &str = ">>>>>>>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub key: String,
+    pub ours: Option<cv::Value>,
+    pub theirs: Option<cv::Value>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Merged {
+    pub data: Data,
+    pub conflicts: Vec<Conflict>,
+}
+
+fn as_set(v: Option<&cv::Value>) -> Vec<cv::Value> {
+    match v {
+        None => vec![],
+        Some(cv::Value::Array(a)) => a.clone(),
+        Some(x) => vec![x.clone()],
+    }
+}
+
+fn is_set(key: &str, vals: &[Option<&cv::Value>]) -> bool {
+    SET_KEYS.contains(&key) ||
+        vals.iter().any(|v| matches!(v, Some(cv::Value::Array(_))))
+}
+
+// element removed by either side is removed, added by either side is added
+fn merge_set(base: Option<&cv::Value>, ours: Option<&cv::Value>,
+             theirs: Option<&cv::Value>) -> Option<cv::Value> {
+    let (b, o, t) = (as_set(base), as_set(ours), as_set(theirs));
+
+    let mut res: Vec<cv::Value> = vec![];
+
+    for x in o.iter().chain(t.iter()) {
+        if res.contains(x) { continue }
+
+        let removed = b.contains(x) && (!o.contains(x) || !t.contains(x));
+        if !removed { res.push(x.clone()) }
+    }
+
+    let was_array = [base, ours, theirs].iter()
+        .any(|v| matches!(v, Some(cv::Value::Array(_))));
+
+    match res.len() {
+        0 => None,
+        1 if !was_array => res.pop(),
+        _ => Some(cv::Value::Array(res)),
+    }
+}
+
+// `file` is side whose file content is taken, None when file not change
+pub fn merge(base: &Data, ours: &Data, theirs: &Data,
+             file: Option<Side>) -> Merged {
+    let keys: BTreeSet<&String> = base.keys()
+        .chain(ours.keys()).chain(theirs.keys()).collect();
+
+    let mut res = Merged { data: BTreeMap::new(), conflicts: vec![] };
+
+    for key in keys {
+        let (b, o, t) = (base.get(key), ours.get(key), theirs.get(key));
+
+        let v = if COMPUTED.contains(&key.as_str()) {
+            match file {
+                Some(Side::Theirs) => t.cloned(),
+                Some(Side::Ours) => o.cloned(),
+                // yaml of both may change, take newer
+                None => o.into_iter().chain(t).max_by(|x, y| {
+                    match (x, y) {
+                        (cv::Value::Integer(x), cv::Value::Integer(y)) => x.cmp(y),
+                        _ => std::cmp::Ordering::Equal,
+                    }
+                }).cloned(),
+            }
+        }
+        else if o == t || b == t { o.cloned() }
+        else if b == o { t.cloned() }
+        else if is_set(key, &[b, o, t]) { merge_set(b, o, t) }
+        else {
+            res.conflicts.push(Conflict { key: key.clone(),
+                                          ours: o.cloned(),
+                                          theirs: t.cloned() });
+            // keep ours until resolved
+            o.cloned()
+        };
+
+        if let Some(v) = v {
+            res.data.insert(key.clone(), v);
+        }
+    }
+
+    res
+}
+
+fn to_yaml(data: &Data) -> Result<String> {
+    let y = serde_yaml::to_string(data)?;
+    let y = y.strip_prefix("---\n").unwrap_or(&y);
+
+    // empty map
+    if y.trim() == "{}" { return Ok("".to_string()) }
+
+    Ok(y.to_string())
+}
+
+// yaml of merged data, each conflict key as a marked hunk
+pub fn gen_conflict_yaml(merged: &Merged, ours: &str, theirs: &str)
+                         -> Result<String> {
+    let mut data = merged.data.clone();
+    for c in merged.conflicts.iter() {
+        data.remove(&c.key);
+    }
+
+    let mut res = "---\n".to_string() + &to_yaml(&data)?;
+
+    for c in merged.conflicts.iter() {
+        let side = |v: &Option<cv::Value>| -> Result<String> {
+            let mut m = BTreeMap::new();
+            if let Some(v) = v { m.insert(c.key.clone(), v.clone()); }
+            to_yaml(&m)
+        };
+
+        res += &format!("{} {}\n{}{}\n{}{} {}\n",
+                        MARK_OURS, ours, side(&c.ours)?,
+                        MARK_SEP, side(&c.theirs)?,
+                        MARK_THEIRS, theirs);
+    }
+
+    Ok(res)
+}
+
+pub fn has_conflict(yaml: &str) -> bool {
+    yaml.lines().any(|l| l.starts_with(MARK_OURS) || l.starts_with(MARK_THEIRS))
+}
+
+// clear markers, keep one side of every hunk
+pub fn resolve(yaml: &str, side: Side) -> Result<String> {
+    // 0: common, 1: ours, 2: theirs
+    let mut st = 0;
+    let mut res = String::new();
+
+    for l in yaml.lines() {
+        if l.starts_with(MARK_OURS) && st == 0 { st = 1; continue }
+        if l == MARK_SEP && st == 1 { st = 2; continue }
+        if l.starts_with(MARK_THEIRS) && st == 2 { st = 0; continue }
+
+        let keep = match st {
+            1 => side == Side::Ours,
+            2 => side == Side::Theirs,
+            _ => true,
+        };
+
+        if keep {
+            res += l;
+            res.push('\n');
+        }
+    }
+
+    if st != 0 { return err("unterminated conflict marker") }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(s: &str) -> cv::Value {
+        cv::Value::Text(s.to_string())
+    }
+
+    fn data(kv: &[(&str, cv::Value)]) -> Data {
+        kv.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+    }
+
+    #[test]
+    fn test_merge() {
+        let tags = |l: &[&str]| cv::Value::Array(l.iter().map(|x| text(x)).collect());
+
+        let base = data(&[("name", text("a")), ("note", text("n")),
+                          ("tag", tags(&["x", "y"])),
+                          ("mtime", cv::Value::Integer(1))]);
+        let ours = data(&[("name", text("b")), ("note", text("o")),
+                          ("tag", tags(&["x", "y", "o"])),
+                          ("mtime", cv::Value::Integer(3))]);
+        let theirs = data(&[("name", text("a")), ("note", text("t")),
+                            ("tag", tags(&["y", "t"])), ("rate", text("5")),
+                            ("mtime", cv::Value::Integer(2))]);
+
+        let m = merge(&base, &ours, &theirs, None);
+
+        assert_eq!(m.data["name"], text("b"));
+        assert_eq!(m.data["rate"], text("5"));
+        assert_eq!(m.data["tag"], tags(&["y", "o", "t"]));
+        assert_eq!(m.data["mtime"], cv::Value::Integer(3));
+
+        assert_eq!(m.conflicts.len(), 1);
+        assert_eq!(m.conflicts[0].key, "note");
+
+        let y = gen_conflict_yaml(&m, "ours", "theirs").unwrap();
+        assert!(has_conflict(&y));
+
+        let t = resolve(&y, Side::Theirs).unwrap();
+        assert!(!has_conflict(&t));
+        let v: Data = serde_yaml::from_str(&t).unwrap();
+        assert_eq!(v["note"], text("t"));
+        assert_eq!(v["tag"], tags(&["y", "o", "t"]));
+
+        let o = resolve(&y, Side::Ours).unwrap();
+        let v: Data = serde_yaml::from_str(&o).unwrap();
+        assert_eq!(v["note"], text("o"));
+    }
+
+    #[test]
+    fn test_merge_scalar_tag() {
+        let base = data(&[]);
+        let ours = data(&[("tag", text("x"))]);
+        let theirs = data(&[("tag", text("y"))]);
+
+        let m = merge(&base, &ours, &theirs, Some(Side::Ours));
+        assert!(m.conflicts.is_empty());
+        assert_eq!(m.data["tag"], cv::Value::Array(vec![text("x"), text("y")]));
+    }
+}
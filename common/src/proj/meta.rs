@@ -0,0 +1,224 @@
+// .meta sidecar, state of anno & file when last saved
+//
+//   version 2
+//   pid <id>            zero or more, parents of anno
+//   fid <id>            file object in store, zero if never committed
+//   anno_hash <id>      of data when saved, see canon.rs
+//   content_hash <id>   sha256 of file content, to find moved file
+//   size <n>            of file in bytes
+//   mtime <n>           of file in ns since epoch
+//
+// id in zbase32, one field per line in this order, anything else is an
+// error. legacy format, "# pid" / "# ref_oid" / "# anno_hash" sections of
+// ids, is still read: version 0 has anno_hash of serde_yaml output, 1 (as
+// "# anno_hash cbor") of canonical cbor. nep-upgrade rewrite them, with
+// content_hash of file as is, trusted unchanged since last save only by
+// equal mtime of file & .meta
+
+use std::iter::{Enumerate, Peekable};
+use std::str::{FromStr, Lines};
+
+use crate::error::*;
+use crate::util::{self, Id};
+
+pub const VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Content {
+    pub hash: Id,
+    pub size: u64,
+    pub mtime: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Meta {
+    pub version: u32,
+
+    pub pid: Vec<Id>,
+    pub fid: Id,
+    pub anno_hash: Id,
+
+    // none in legacy format
+    pub content: Option<Content>,
+}
+
+fn bad<T>(n: usize, msg: &str) -> Result<T> {
+    err(&format!("line {}: {}", n, msg))
+}
+
+// 52 chars, and nothing lost in decode
+fn parse_id(s: &str) -> Option<Id> {
+    let id = util::zbase32_to_id(s);
+
+    if s.len() == 52 && util::to_zbase32(&id) == s { Some(id) } else { None }
+}
+
+fn parse_legacy(content: &str) -> Result<Meta> {
+    let mut version = 0;
+    let mut section = "";
+
+    let mut pid = vec![];
+    let (mut fid, mut anno_hash) = (None, None);
+
+    for (i, l) in content.lines().enumerate() {
+        let l = l.trim_end();
+
+        match l {
+            "" => continue,
+            "# pid" | "# ref_oid" | "# anno_hash" | "# anno_hash cbor" => {
+                if l == "# anno_hash cbor" { version = 1; }
+                section = l;
+                continue
+            },
+            _ => (),
+        }
+
+        let id = match parse_id(l) {
+            Some(id) => id,
+            None => return bad(i + 1, &format!("invalid id '{}'", l)),
+        };
+
+        let slot = match section {
+            "# pid" => { pid.push(id); continue },
+            "# ref_oid" => &mut fid,
+            "" => return bad(i + 1, "id before section"),
+            _ => &mut anno_hash,
+        };
+
+        if slot.replace(id).is_some() {
+            return bad(i + 1, &format!("more than one id in '{}'", section))
+        }
+    }
+
+    match (fid, anno_hash) {
+        (Some(fid), Some(anno_hash)) => Ok(Meta { version, pid, fid, anno_hash,
+                                                  content: None }),
+        (None, _) => err("no ref_oid"),
+        _ => err("no anno_hash"),
+    }
+}
+
+struct Fields<'a> {
+    lines: Peekable<Enumerate<Lines<'a>>>,
+    // line number of last one
+    n: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn next_is(&mut self, key: &str) -> bool {
+        matches!(self.lines.peek(), Some((_, l)) if l.split(' ').next() == Some(key))
+    }
+
+    fn get(&mut self, key: &str) -> Result<&'a str> {
+        let (i, l) = match self.lines.next() {
+            Some(x) => x,
+            None => return bad(self.n + 1, &format!("expect {}, got end of file", key)),
+        };
+        self.n = i + 1;
+
+        match l.split_once(' ') {
+            Some((k, v)) if k == key && !v.is_empty() => Ok(v),
+            _ => bad(self.n, &format!("expect '{} <value>', got '{}'", key, l)),
+        }
+    }
+
+    fn id(&mut self, key: &str) -> Result<Id> {
+        let v = self.get(key)?;
+
+        match parse_id(v) {
+            Some(id) => Ok(id),
+            None => bad(self.n, &format!("invalid id '{}'", v)),
+        }
+    }
+
+    fn num<T: FromStr>(&mut self, key: &str) -> Result<T> {
+        let v = self.get(key)?;
+
+        match v.parse() {
+            Ok(x) => Ok(x),
+            Err(_) => bad(self.n, &format!("invalid {} '{}'", key, v)),
+        }
+    }
+}
+
+pub fn parse(content: &str) -> Result<Meta> {
+    if content.starts_with("# ") { return parse_legacy(content) }
+
+    let mut f = Fields { lines: content.lines().enumerate().peekable(), n: 0 };
+
+    let version: u32 = f.num("version")?;
+    if version != VERSION {
+        return bad(1, &format!("version {} not supported, expect {}", version, VERSION))
+    }
+
+    let mut pid = vec![];
+    while f.next_is("pid") { pid.push(f.id("pid")?); }
+
+    let fid = f.id("fid")?;
+    let anno_hash = f.id("anno_hash")?;
+    let content = Content { hash: f.id("content_hash")?, size: f.num("size")?,
+                            mtime: f.num("mtime")? };
+
+    if let Some((i, l)) = f.lines.next() {
+        return bad(i + 1, &format!("unexpected '{}'", l))
+    }
+
+    Ok(Meta { version, pid, fid, anno_hash, content: Some(content) })
+}
+
+impl Meta {
+    // always as current version
+    pub fn gen(&self) -> Result<String> {
+        let c = match self.content {
+            Some(c) => c,
+            None => return err("no content to save in meta"),
+        };
+
+        let mut res = format!("version {}\n", VERSION);
+
+        for p in self.pid.iter() {
+            res += &format!("pid {}\n", util::to_zbase32(p));
+        }
+
+        res += &format!("fid {}\n", util::to_zbase32(&self.fid));
+        res += &format!("anno_hash {}\n", util::to_zbase32(&self.anno_hash));
+        res += &format!("content_hash {}\n", util::to_zbase32(&c.hash));
+        res += &format!("size {}\nmtime {}\n", c.size, c.mtime);
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_meta() {
+        let meta = Meta { version: VERSION, pid: vec![[1; 32], [2; 32]], fid: [3; 32],
+                          anno_hash: [4; 32],
+                          content: Some(Content { hash: [5; 32], size: 42,
+                                                  mtime: 1600000000123456789 }) };
+
+        let text = meta.gen().unwrap();
+        assert_eq!(parse(&text).unwrap(), meta);
+
+        let bad = |s: &str| parse(s).unwrap_err().to_string();
+        assert!(bad(&text.replace("version 2", "version 3")).contains("line 1:"));
+        assert!(bad(&text.replace("size 42", "size 4x")).contains("line 7: invalid size"));
+        assert!(bad(&(text.clone() + "\n")).contains("line 9: unexpected"));
+        assert!(bad(&text.replace("fid ", "fid  ")).contains("line 4: invalid id"));
+        assert!(bad(&text.replace("mtime", "mtim")).contains("expect 'mtime"));
+
+        // legacy
+        let z = |b: u8| util::to_zbase32(&[b; 32]);
+        let old = format!("# pid\n{}\n\n# ref_oid\n{}\n\n# anno_hash\n{}\n", z(1), z(3), z(4));
+        let m = parse(&old).unwrap();
+        assert_eq!((m.version, m.pid, m.fid, m.anno_hash, m.content),
+                   (0, vec![[1; 32]], [3; 32], [4; 32], None));
+
+        assert_eq!(parse(&old.replace("# anno_hash", "# anno_hash cbor")).unwrap().version, 1);
+        assert!(parse(&old.replace(&z(1), &z(1)[..51])).is_err());
+        assert!(parse(&old.replace(&z(4), &format!("{}\n{}", z(4), z(5)))).is_err());
+    }
+}
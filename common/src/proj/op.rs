@@ -0,0 +1,457 @@
+// edit operation of anno, one per argument of nep-meta
+//
+//   +val           add tag, same as tag+val
+//   -val           remove tag, same as tag-val
+//   key=val        set
+//   key=[]         set to empty list
+//   key+val        append to list
+//   key-val        remove from list
+//   key+=n         add number, key-=n subtract
+//   key!           unset key
+//   key~new        rename key
+//   key:old=new    replace old by new, in list or single value
+//
+// key end at first of = + - ! ~ :, so value may contain any of them.
+// \ escape next char, '...' quote literally and "..." quote with \ escape,
+// e.g. "my-key"=1, 'note=-x' is set, note+\=x append "=x"
+//
+// an argument naming an existing file is a path even with op char, e.g.
+// a:b.txt, ops end at first path or "--"
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde_cbor::value as cv;
+
+use crate::error::*;
+use crate::proj::anno::REMOVED;
+use crate::proj::schema::Schema;
+
+pub const OPS: &[char] = &['=', '+', '-', '!', '~', ':'];
+
+const TAG: &str = "tag";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Set(String, String),
+    Clear(String),
+    Add(String, String),
+    Del(String, String),
+    Incr(String, f64),
+    Unset(String),
+    Rename(String, String),
+    Replace(String, String, String),
+}
+
+// chars of an op, with quote & escape
+struct Scan<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Scan<'a> {
+    // unquoted until one of `stop`, which is consumed and returned
+    fn until(&mut self, stop: &[char]) -> Result<(String, Option<char>)> {
+        let mut res = String::new();
+
+        while let Some(c) = self.chars.next() {
+            match c {
+                '\\' => match self.chars.next() {
+                    Some(x) => res.push(x),
+                    None => return err("trailing \\"),
+                },
+                '\'' => loop {
+                    match self.chars.next() {
+                        Some('\'') => break,
+                        Some(x) => res.push(x),
+                        None => return err("unclosed '"),
+                    }
+                },
+                '"' => loop {
+                    match self.chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match self.chars.next() {
+                            Some(x) => res.push(x),
+                            None => return err("unclosed \""),
+                        },
+                        Some(x) => res.push(x),
+                        None => return err("unclosed \""),
+                    }
+                },
+                c if stop.contains(&c) => return Ok((res, Some(c))),
+                c => res.push(c),
+            }
+        }
+
+        Ok((res, None))
+    }
+
+    fn rest(&mut self) -> Result<String> {
+        Ok(self.until(&[])?.0)
+    }
+
+    fn next_is(&mut self, c: char) -> bool {
+        self.chars.next_if_eq(&c).is_some()
+    }
+}
+
+fn quote(s: &str, special: &[char]) -> String {
+    let mut res = String::new();
+
+    for c in s.chars() {
+        if c == '\\' || c == '\'' || c == '"' || special.contains(&c) {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+
+    res
+}
+
+impl Op {
+    pub fn parse(s: &str) -> Result<Op> {
+        Self::parse_(s).map_err(|e| match e {
+            Error::Simple(m) => err_simple(&format!("invalid op '{}', {}", s, m)),
+            e => e,
+        })
+    }
+
+    fn parse_(s: &str) -> Result<Op> {
+        let mut sc = Scan { chars: s.chars().peekable() };
+
+        // tag shorthand
+        if sc.next_is('+') { return Ok(Op::Add(TAG.into(), sc.rest()?)) }
+        if sc.next_is('-') { return Ok(Op::Del(TAG.into(), sc.rest()?)) }
+
+        let (key, op) = sc.until(OPS)?;
+
+        if key.is_empty() { return err("missing key") }
+
+        let res = match op {
+            None => return err("missing operator"),
+            Some('=') if sc.chars.clone().eq("[]".chars()) => Op::Clear(key),
+            Some('=') => Op::Set(key, sc.rest()?),
+            Some(c) if (c == '+' || c == '-') && sc.next_is('=') => {
+                let n = sc.rest()?;
+                match n.trim().parse::<f64>() {
+                    Ok(x) if x.is_finite() => Op::Incr(key, if c == '+' { x } else { -x }),
+                    _ => return err(&format!("'{}' is not number", n)),
+                }
+            },
+            Some('+') => Op::Add(key, sc.rest()?),
+            Some('-') => Op::Del(key, sc.rest()?),
+            Some('!') => {
+                if !sc.rest()?.is_empty() { return err("unexpected after !") }
+                Op::Unset(key)
+            },
+            Some('~') => match sc.rest()? {
+                n if n.is_empty() => return err("missing new key"),
+                n => Op::Rename(key, n),
+            },
+            Some(_) => match sc.until(&['='])? {
+                (old, Some(_)) => Op::Replace(key, old, sc.rest()?),
+                _ => return err("missing = of replace"),
+            },
+        };
+
+        Ok(res)
+    }
+
+    pub fn key(&self) -> &str {
+        match self {
+            Op::Set(k, _) | Op::Clear(k) | Op::Add(k, _) | Op::Del(k, _) |
+            Op::Incr(k, _) | Op::Unset(k) | Op::Rename(k, _) |
+            Op::Replace(k, _, _) => k,
+        }
+    }
+
+    // apply to data, coerced by schema, return whether changed; undeclared
+    // key is skipped unless `allow_new`. data is not changed on error
+    pub fn apply(&self, data: &mut BTreeMap<String, cv::Value>, schema: &Schema,
+                 allow_new: bool) -> Result<bool> {
+        let key = self.key();
+
+        if key == REMOVED {
+            return err(&format!("{} is reserved", key))
+        }
+
+        // old key may be undeclared, e.g. fix it for strict schema
+        match self {
+            Op::Unset(_) | Op::Rename(_, _) if schema.is_builtin(key) => {
+                return err(&format!("{} is set by nephrite", key))
+            },
+            Op::Unset(_) | Op::Rename(_, _) if !data.contains_key(key) => return Ok(false),
+            Op::Unset(_) => return Ok(data.remove(key).is_some()),
+            Op::Rename(_, new) => {
+                if data.contains_key(new) {
+                    return err(&format!("{} already exists", new))
+                }
+
+                if schema.is_builtin(new) {
+                    return err(&format!("{} is set by nephrite", new))
+                }
+
+                match schema.field_for(new, allow_new)? {
+                    Some(f) => f.check_all(&data[key])?,
+                    None => return err(&format!("{} is not known, use --new", new)),
+                }
+
+                let v = data.remove(key).unwrap();
+                data.insert(new.clone(), v);
+                return Ok(true)
+            },
+            _ => (),
+        }
+
+        let field = match schema.field_for(key, allow_new || data.contains_key(key))? {
+            Some(f) => f,
+            None => return Ok(false),
+        };
+
+        // as typed, or text stored before schema
+        let matcher = |val: &str| {
+            let vstr = cv::Value::Text(val.to_string());
+            let v = field.coerce(val).unwrap_or_else(|_| vstr.clone());
+            move |x: &cv::Value| x == &v || x == &vstr
+        };
+
+        let old = data.get(key);
+
+        let new = match self {
+            Op::Set(_, val) => Some(field.coerce(val)?),
+
+            Op::Clear(_) if !field.many => {
+                return err(&format!("{} take one value, can not be a list", key))
+            },
+            Op::Clear(_) => Some(cv::Value::Array(vec![])),
+
+            Op::Add(_, val) => {
+                let v = field.coerce(val)?;
+                match old {
+                    None => Some(v),
+                    Some(cv::Value::Array(a)) if a.contains(&v) => return Ok(false),
+                    Some(cv::Value::Array(a)) => {
+                        let mut a = a.clone();
+                        a.push(v);
+                        Some(cv::Value::Array(a))
+                    },
+                    Some(x) if x == &v => return Ok(false),
+                    Some(x) if field.many => Some(cv::Value::Array(vec![x.clone(), v])),
+                    Some(_) => {
+                        return err(&format!("{} take one value, use {}={}",
+                                            key, key, quote(val, &[])))
+                    },
+                }
+            },
+
+            Op::Del(_, val) => {
+                let hit = matcher(val);
+                match old {
+                    None => return Ok(false),
+                    Some(cv::Value::Array(a)) if !a.iter().any(&hit) => {
+                        return Ok(false)
+                    },
+                    Some(cv::Value::Array(a)) => {
+                        let a: Vec<cv::Value> = a.iter().filter(|x| !hit(x))
+                            .cloned().collect();
+                        if a.is_empty() { None } else { Some(cv::Value::Array(a)) }
+                    },
+                    Some(x) if hit(x) => None,
+                    Some(_) => return Ok(false),
+                }
+            },
+
+            Op::Incr(_, n) => {
+                let cur = match old {
+                    None => 0.0,
+                    Some(cv::Value::Integer(i)) => *i as f64,
+                    Some(cv::Value::Float(f)) => *f,
+                    Some(cv::Value::Text(s)) => match s.trim().parse::<f64>() {
+                        Ok(x) => x,
+                        Err(_) => return err(&format!("{} is not a number", key)),
+                    },
+                    Some(_) => return err(&format!("{} is not a number", key)),
+                };
+
+                Some(field.coerce(&(cur + n).to_string())?)
+            },
+
+            Op::Replace(_, from, to) => {
+                let hit = matcher(from);
+                let v = field.coerce(to)?;
+
+                match old {
+                    None => return Ok(false),
+                    Some(cv::Value::Array(a)) if !a.iter().any(&hit) => {
+                        return Ok(false)
+                    },
+                    Some(cv::Value::Array(a)) => {
+                        let mut res: Vec<cv::Value> = vec![];
+
+                        // no duplicate after replace
+                        for x in a.iter() {
+                            let x = if hit(x) { v.clone() } else { x.clone() };
+                            if !res.contains(&x) { res.push(x); }
+                        }
+
+                        Some(cv::Value::Array(res))
+                    },
+                    Some(x) if hit(x) => Some(v),
+                    Some(_) => return Ok(false),
+                }
+            },
+
+            Op::Unset(_) | Op::Rename(_, _) => unreachable!(),
+        };
+
+        let res = data.get(key) != new.as_ref();
+
+        match new {
+            Some(v) => { data.insert(key.to_string(), v); },
+            None => { data.remove(key); },
+        }
+
+        Ok(res)
+    }
+}
+
+impl FromStr for Op {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Op> {
+        Op::parse(s)
+    }
+}
+
+// parse back to the same op
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let k = |k: &str| quote(k, OPS);
+        let v = |v: &str| quote(v, &[]);
+
+        match self {
+            Op::Set(key, val) if val == "[]" => write!(f, "{}=\\[]", k(key)),
+            Op::Set(key, val) => write!(f, "{}={}", k(key), v(val)),
+            Op::Clear(key) => write!(f, "{}=[]", k(key)),
+            // not += or -=
+            Op::Add(key, val) if val.starts_with('=') => {
+                write!(f, "{}+\\{}", k(key), v(val))
+            },
+            Op::Add(key, val) => write!(f, "{}+{}", k(key), v(val)),
+            Op::Del(key, val) if val.starts_with('=') => {
+                write!(f, "{}-\\{}", k(key), v(val))
+            },
+            Op::Del(key, val) => write!(f, "{}-{}", k(key), v(val)),
+            Op::Incr(key, n) if *n < 0.0 => write!(f, "{}-={}", k(key), -n),
+            Op::Incr(key, n) => write!(f, "{}+={}", k(key), n),
+            Op::Unset(key) => write!(f, "{}!", k(key)),
+            Op::Rename(key, new) => write!(f, "{}~{}", k(key), v(new)),
+            Op::Replace(key, from, to) => {
+                write!(f, "{}:{}={}", k(key), quote(from, &['=']), v(to))
+            },
+        }
+    }
+}
+
+// stop at first error, return count of changed
+pub fn apply_all(data: &mut BTreeMap<String, cv::Value>, schema: &Schema,
+                 ops: &[Op], allow_new: bool) -> Result<u64> {
+    let mut cnt = 0;
+
+    for op in ops.iter() {
+        if op.apply(data, schema, allow_new)? { cnt += 1 }
+    }
+
+    Ok(cnt)
+}
+
+// nep-meta arguments to (ops, paths, --new)
+pub fn split_args<F>(args: Vec<String>, exists: F) -> (Vec<String>, Vec<String>, bool)
+    where F: Fn(&str) -> bool
+{
+    let (mut ops, mut paths) = (vec![], vec![]);
+    let mut allow_new = false;
+
+    let mut args = args.into_iter();
+
+    for a in args.by_ref() {
+        match a.as_str() {
+            "--new" => allow_new = true,
+            "--" => break,
+            _ if a.contains(OPS) && !exists(&a) => ops.push(a),
+            _ => { paths.push(a); break },
+        }
+    }
+
+    paths.extend(args);
+
+    (ops, paths, allow_new)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let cases = vec![
+            ("+x", Op::Add("tag".into(), "x".into())),
+            ("-x-y", Op::Del("tag".into(), "x-y".into())),
+            ("day=2020-01-02", Op::Set("day".into(), "2020-01-02".into())),
+            ("note=+x", Op::Set("note".into(), "+x".into())),
+            ("\"my-key\"=1", Op::Set("my-key".into(), "1".into())),
+            ("note+\\=x", Op::Add("note".into(), "=x".into())),
+            ("note-'a b'", Op::Del("note".into(), "a b".into())),
+            ("tag=[]", Op::Clear("tag".into())),
+            ("tag='[]'", Op::Set("tag".into(), "[]".into())),
+            ("rate+=1", Op::Incr("rate".into(), 1.0)),
+            ("rate-=0.5", Op::Incr("rate".into(), -0.5)),
+            ("level!", Op::Unset("level".into())),
+            ("tag~label", Op::Rename("tag".into(), "label".into())),
+            ("tag:a\\=b=c", Op::Replace("tag".into(), "a=b".into(), "c".into())),
+        ];
+
+        for (s, op) in cases.into_iter() {
+            assert_eq!(Op::parse(s).unwrap(), op, "{}", s);
+            assert_eq!(Op::parse(&op.to_string()).unwrap(), op, "{}", s);
+        }
+
+        for s in ["a.txt", "=x", "x!y", "rate+=a", "x:y", "'x=1"].iter() {
+            assert!(Op::parse(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let schema = Schema::parse("[field.rate]\ntype = \"int\"\nmax = 5\n").unwrap();
+        let ops: Vec<Op> = ["rate=3", "rate+=1", "+a", "+b", "tag:a=b", "note+x",
+                            "note~memo"]
+            .iter().map(|s| s.parse().unwrap()).collect();
+
+        let mut data = BTreeMap::new();
+        assert_eq!(apply_all(&mut data, &schema, &ops, true).unwrap(), 7);
+
+        assert_eq!(data["rate"], cv::Value::Integer(4));
+        assert_eq!(data["tag"], cv::Value::Array(vec![cv::Value::Text("b".into())]));
+        assert_eq!(data["memo"], cv::Value::Text("x".into()));
+        assert!(!data.contains_key("note"));
+
+        assert!(Op::parse("rate+=2").unwrap().apply(&mut data, &schema, false).is_err());
+        assert_eq!(data["rate"], cv::Value::Integer(4));
+
+        assert!(Op::parse("size!").unwrap().apply(&mut data, &schema, false).is_err());
+    }
+
+    #[test]
+    fn test_split_args() {
+        let args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+        let exists = |a: &str| a == "a:b.txt" || a == "notes~";
+
+        assert_eq!(split_args(args("--new +x a:b.txt y=1"), exists),
+                   (args("+x"), args("a:b.txt y=1"), true));
+        assert_eq!(split_args(args("x:y=z notes~"), exists),
+                   (args("x:y=z"), args("notes~"), false));
+        assert_eq!(split_args(args("+x -- c:d"), exists),
+                   (args("+x"), args("c:d"), false));
+    }
+}
@@ -0,0 +1,403 @@
+// anno schema of project, .manifest/schema.toml
+//
+//   strict = true          # reject key not declared, default false
+//
+//   [field.rate]
+//   type = "int"           # int, float, bool, date, enum, tag or text
+//   min = 0                # int & float only
+//   max = 5
+//
+//   [field.level]
+//   type = "enum"
+//   values = ["low", "mid", "high"]
+//
+//   [field.note]
+//   type = "text"
+//   many = true            # one or a list of value, tag is always many
+//   required = false
+//
+// date is "YYYY-MM-DD"; values limit text & tag too, when given.
+// name, type, size and mtime are set by nephrite, can not be declared.
+// without schema.toml, predefined key take any value as before
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::NaiveDate;
+use serde_cbor::value as cv;
+
+use crate::error::*;
+use crate::proj::anno::REMOVED;
+
+pub const SCHEMA: &str = "schema.toml";
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+// written by update_meta
+const BUILTIN: &[(&str, Type)] = &[
+    ("name", Type::Text), ("type", Type::Text),
+    ("size", Type::Int), ("mtime", Type::Int),
+];
+
+// user key known without schema.toml
+const PREDEFINED: &[&str] = &["note", "rate", "tag", "node", "level"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    // untyped, as text when set
+    Any,
+    Int,
+    Float,
+    Bool,
+    Date,
+    Enum,
+    Tag,
+    Text,
+}
+
+impl Type {
+    fn parse(s: &str) -> Option<Type> {
+        Some(match s {
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "bool" => Type::Bool,
+            "date" => Type::Date,
+            "enum" => Type::Enum,
+            "tag" => Type::Tag,
+            "text" => Type::Text,
+            _ => return None,
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Type::Any => "any",
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Bool => "bool",
+            Type::Date => "date",
+            Type::Enum => "enum",
+            Type::Tag => "tag",
+            Type::Text => "text",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub tp: Type,
+    pub many: bool,
+    pub required: bool,
+
+    // allowed, empty for any
+    pub values: Vec<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl Field {
+    fn new(tp: Type, many: bool) -> Field {
+        Field { tp, many, required: false, values: vec![], min: None, max: None }
+    }
+
+    fn in_range(&self, x: f64) -> bool {
+        self.min.is_none_or(|m| x >= m) && self.max.is_none_or(|m| x <= m)
+    }
+
+    fn check_values(&self, s: &str) -> Result<()> {
+        if !self.values.is_empty() && !self.values.iter().any(|v| v == s) {
+            return err(&format!("'{}' not one of {}", s, self.values.join(", ")))
+        }
+        Ok(())
+    }
+
+    // value from command line
+    pub fn coerce(&self, s: &str) -> Result<cv::Value> {
+        let res = match self.tp {
+            Type::Any => cv::Value::Text(s.to_string()),
+            Type::Int => match s.trim().parse::<i64>() {
+                Ok(i) => cv::Value::Integer(i as i128),
+                Err(_) => return err(&format!("'{}' is not int", s)),
+            },
+            Type::Float => match s.trim().parse::<f64>() {
+                Ok(f) if f.is_finite() => cv::Value::Float(f),
+                _ => return err(&format!("'{}' is not float", s)),
+            },
+            Type::Bool => match s.trim() {
+                "true" | "yes" | "1" => cv::Value::Bool(true),
+                "false" | "no" | "0" => cv::Value::Bool(false),
+                _ => return err(&format!("'{}' is not bool", s)),
+            },
+            Type::Date => match NaiveDate::parse_from_str(s.trim(), DATE_FORMAT) {
+                Ok(d) => cv::Value::Text(d.format(DATE_FORMAT).to_string()),
+                Err(_) => return err(&format!("'{}' is not date, expect YYYY-MM-DD", s)),
+            },
+            Type::Enum | Type::Tag | Type::Text => cv::Value::Text(s.to_string()),
+        };
+
+        self.check(&res)?;
+        Ok(res)
+    }
+
+    // single value
+    pub fn check(&self, v: &cv::Value) -> Result<()> {
+        match (self.tp, v) {
+            (Type::Any, _) => Ok(()),
+            (Type::Int, cv::Value::Integer(i)) if self.in_range(*i as f64) => Ok(()),
+            (Type::Float, cv::Value::Float(f)) if self.in_range(*f) => Ok(()),
+            (Type::Float, cv::Value::Integer(i)) if self.in_range(*i as f64) => Ok(()),
+            (Type::Int, cv::Value::Integer(_)) | (Type::Float, cv::Value::Float(_)) |
+            (Type::Float, cv::Value::Integer(_)) => {
+                err(&format!("{} out of range {}..{}", show(v),
+                             self.min.map_or("".to_string(), |m| m.to_string()),
+                             self.max.map_or("".to_string(), |m| m.to_string())))
+            },
+            (Type::Bool, cv::Value::Bool(_)) => Ok(()),
+            (Type::Date, cv::Value::Text(s)) => {
+                match NaiveDate::parse_from_str(s, DATE_FORMAT) {
+                    Ok(d) if d.format(DATE_FORMAT).to_string() == *s => Ok(()),
+                    _ => err(&format!("'{}' is not date, expect YYYY-MM-DD", s)),
+                }
+            },
+            (Type::Enum, cv::Value::Text(s)) | (Type::Tag, cv::Value::Text(s)) |
+            (Type::Text, cv::Value::Text(s)) => self.check_values(s),
+            _ => err(&format!("{} is not {}", show(v), self.tp.name())),
+        }
+    }
+
+    // value in anno, list only when many
+    pub fn check_all(&self, v: &cv::Value) -> Result<()> {
+        match v {
+            cv::Value::Array(a) if self.many => {
+                a.iter().map(|x| self.check(x)).collect::<Result<Vec<_>>>()?;
+                Ok(())
+            },
+            cv::Value::Array(_) => err("take one value, got a list"),
+            x => self.check(x),
+        }
+    }
+}
+
+// without SimpleError prefix, to join with other
+fn msg(e: &Error) -> String {
+    match e {
+        Error::Simple(m) => m.clone(),
+        e => e.to_string(),
+    }
+}
+
+fn show(v: &cv::Value) -> String {
+    match v {
+        cv::Value::Text(s) => format!("'{}'", s),
+        cv::Value::Integer(i) => i.to_string(),
+        cv::Value::Float(f) => f.to_string(),
+        cv::Value::Bool(b) => b.to_string(),
+        x => format!("{:?}", x),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    pub fields: BTreeMap<String, Field>,
+    pub strict: bool,
+}
+
+impl Default for Schema {
+    fn default() -> Schema {
+        let mut res = Schema::builtin();
+
+        for k in PREDEFINED.iter() {
+            res.fields.insert(k.to_string(), Field::new(Type::Any, true));
+        }
+
+        res
+    }
+}
+
+impl Schema {
+    fn builtin() -> Schema {
+        Schema {
+            fields: BUILTIN.iter()
+                .map(|(k, tp)| (k.to_string(), Field::new(*tp, false))).collect(),
+            strict: false,
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Schema> {
+        let table: toml::value::Table = match toml::from_str(content) {
+            Ok(t) => t,
+            Err(e) => return err(&e.to_string()),
+        };
+
+        let mut res = Schema::builtin();
+
+        for (k, v) in table.iter() {
+            match (k.as_str(), v) {
+                ("strict", toml::Value::Boolean(b)) => res.strict = *b,
+                ("field", toml::Value::Table(t)) => {
+                    for (name, f) in t.iter() {
+                        if res.fields.contains_key(name) || name == REMOVED {
+                            return err(&format!("field {} is reserved", name))
+                        }
+
+                        let f = Self::parse_field(f)
+                            .map_err(|e| err_simple(&format!("field {}: {}", name, msg(&e))))?;
+                        res.fields.insert(name.clone(), f);
+                    }
+                },
+                _ => return err(&format!("unknown key {}", k)),
+            }
+        }
+
+        Ok(res)
+    }
+
+    fn parse_field(v: &toml::Value) -> Result<Field> {
+        let t = match v {
+            toml::Value::Table(t) => t,
+            _ => return err("should be a table"),
+        };
+
+        let tp = match t.get("type") {
+            Some(toml::Value::String(s)) => match Type::parse(s) {
+                Some(tp) => tp,
+                None => return err(&format!("unknown type {}", s)),
+            },
+            _ => return err("type missing"),
+        };
+
+        let mut res = Field::new(tp, tp == Type::Tag);
+
+        for (k, v) in t.iter() {
+            match (k.as_str(), v) {
+                ("type", _) => (),
+                ("many", toml::Value::Boolean(b)) if tp != Type::Tag => res.many = *b,
+                ("required", toml::Value::Boolean(b)) => res.required = *b,
+                ("values", toml::Value::Array(a)) => {
+                    res.values = a.iter().map(|x| match x {
+                        toml::Value::String(s) => Ok(s.clone()),
+                        _ => err("values should be string"),
+                    }).collect::<Result<_>>()?;
+                },
+                ("min", toml::Value::Integer(i)) => res.min = Some(*i as f64),
+                ("min", toml::Value::Float(f)) => res.min = Some(*f),
+                ("max", toml::Value::Integer(i)) => res.max = Some(*i as f64),
+                ("max", toml::Value::Float(f)) => res.max = Some(*f),
+                _ => return err(&format!("invalid {} for {} field", k, tp.name())),
+            }
+        }
+
+        if tp == Type::Enum && res.values.is_empty() {
+            return err("enum without values")
+        }
+
+        if (res.min.is_some() || res.max.is_some()) &&
+            tp != Type::Int && tp != Type::Float {
+            return err("min/max for int or float only")
+        }
+
+        Ok(res)
+    }
+
+    // default one if no schema.toml
+    pub fn load(mdir: &str) -> Result<Schema> {
+        let path = Path::new(mdir).join(SCHEMA);
+
+        match fs::read_to_string(&path) {
+            Ok(c) => Self::parse(&c)
+                .map_err(|e| err_simple(&format!("{:?}: {}", path, msg(&e)))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Schema::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn is_builtin(&self, key: &str) -> bool {
+        BUILTIN.iter().any(|(k, _)| *k == key)
+    }
+
+    pub fn field(&self, key: &str) -> Option<&Field> {
+        self.fields.get(key)
+    }
+
+    // field of key may be set, undeclared one only when allow_new
+    pub fn field_for(&self, key: &str, allow_new: bool) -> Result<Option<Field>> {
+        if key == REMOVED {
+            return err(&format!("{} is reserved", key))
+        }
+
+        match self.fields.get(key) {
+            Some(f) => Ok(Some(f.clone())),
+            None if self.strict => err(&format!("{} not in schema", key)),
+            None if allow_new => Ok(Some(Field::new(Type::Any, true))),
+            None => Ok(None),
+        }
+    }
+
+    // all problem of data, one per line
+    pub fn validate(&self, data: &BTreeMap<String, cv::Value>) -> Result<()> {
+        let mut msgs = vec![];
+
+        for (k, v) in data.iter() {
+            match self.fields.get(k) {
+                Some(f) => if let Err(e) = f.check_all(v) {
+                    msgs.push(format!("{}: {}", k, msg(&e)));
+                },
+                None if self.strict && k != REMOVED => {
+                    msgs.push(format!("{}: not in schema", k));
+                },
+                None => (),
+            }
+        }
+
+        for (k, f) in self.fields.iter() {
+            if f.required && !data.contains_key(k) {
+                msgs.push(format!("{}: required", k));
+            }
+        }
+
+        if msgs.is_empty() { Ok(()) } else { err(&msgs.join("\n")) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_schema() {
+        let s = Schema::parse(concat!(
+            "strict = true\n",
+            "[field.rate]\ntype = \"int\"\nmin = 0\nmax = 5\n",
+            "[field.level]\ntype = \"enum\"\nvalues = [\"low\", \"high\"]\n",
+            "[field.day]\ntype = \"date\"\nrequired = true\n",
+            "[field.tag]\ntype = \"tag\"\n")).unwrap();
+
+        let rate = s.field("rate").unwrap();
+        assert_eq!(rate.coerce("5").unwrap(), cv::Value::Integer(5));
+        assert!(rate.coerce("6").is_err());
+        assert!(rate.coerce("x").is_err());
+
+        assert!(s.field("level").unwrap().coerce("mid").is_err());
+        assert_eq!(s.field("day").unwrap().coerce("2020-1-2").unwrap(),
+                   cv::Value::Text("2020-01-02".into()));
+
+        assert!(s.field_for("other", true).is_err());
+        assert!(Schema::parse("[field.size]\ntype = \"int\"\n").is_err());
+
+        let mut data = BTreeMap::new();
+        data.insert("name".to_string(), cv::Value::Text("a".into()));
+        data.insert("rate".to_string(), cv::Value::Text("5".into()));
+        data.insert("tag".to_string(), cv::Value::Array(vec![
+            cv::Value::Text("a".into()), cv::Value::Text("b".into())]));
+        assert!(s.validate(&data).is_err());
+
+        data.insert("rate".to_string(), cv::Value::Integer(5));
+        data.insert("day".to_string(), cv::Value::Text("2020-01-02".into()));
+        assert!(s.validate(&data).is_ok());
+
+        // legacy, any value
+        assert!(Schema::default().validate(&data).is_ok());
+    }
+}
@@ -1,8 +1,8 @@
-use std::{collections::{BTreeMap, BTreeSet}, process::{self, Command, Stdio}};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
 use crate::util;
 use crate::conf::Conf;
-use crate::proj::{anno, manifest};
+use crate::proj::{anno, journal, manifest};
 use util::Id;
 
 use crate::error::*;
@@ -13,37 +13,31 @@ use std::io::Write;
 
 use std::io::BufReader;
 
-// for read_until
-use std::io::BufRead;
-// for read_to_string
-use std::io::Read;
-
 //use std::ffi::OsString;
 
 use anno::{St, Anno};
 use manifest::Manifest;
+use journal::Journal;
 
 use chrono::prelude::*;
 
-use serde_cbor::value as cv;
 
 use hex;
+use serde_cbor::value as cv;
 use std::str;
 
-use log::{debug, info, warn};
+use log::debug;
 
 use crate::git;
 use git::Oid;
 
-const BUP_CMD: &'static str = "bup256";
-const ENV_BUP_DIR: &'static str = "BUP_DIR";
-const ENV_BUP_FORCE_TTY: &'static str = "BUP_FORCE_TTY";
+use crate::hashsplit;
 
-const ENV_GIT_DIR: &'static str = "GIT_DIR";
-const ENV_GIT_AUTHOR_DATE: &'static str = "GIT_AUTHOR_DATE";
-const ENV_GIT_COMMITTER_DATE: &'static str = "GIT_COMMITTER_DATE";
+use std::fs::{self, File};
+use std::path::Path;
+use std::thread;
 
-const OID_LEN: usize = 32;
+use filetime::FileTime;
 
 fn min_uniq_len(list: &Vec<Id>) -> usize {
     let len = list.len();
@@ -71,12 +65,39 @@ pub struct Store {
 
     date: u64,
     zone: String,
+
+    odb: git::odb::Odb,
+    // fallback when object not found by odb
+    batch: git::batch::Batch,
+    git_conf: git::config::Config,
+
+    pub quiet: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct CommitResult {
     pub obj_list: Vec<Id>,
     pub oid: Option<Id>,
+    pub tree: Option<Id>,
+
+    // rpath, anno commit, resumed from journal
+    pub annos: Vec<(String, Id, bool)>,
+}
+
+// progress message, off when quiet, e.g. for json output
+macro_rules! say {
+    ($s:expr, $($arg:tt)*) => { if !$s.quiet { println!($($arg)*) } };
+}
+
+// one submodule for each anno commit in changeset tree
+pub fn gen_gitmodules(tree: &git::Tree) -> String {
+    let mut lns = vec![];
+    for entry in tree.iter().filter(|te| te.mode == git::Type::Commit) {
+        lns.push(format!("[submodule \"{}\"]\npath={}\nurl=./\n",
+                         entry.name, entry.name));
+    }
+
+    lns.join("")
 }
 
 pub fn ref_remote(name: &str) -> String {
@@ -87,12 +108,33 @@ pub fn ref_local(name: &str) -> String {
     format!("refs/heads/{}", name)
 }
 
+// tip of anno, named by root commit of pid chain
+pub fn ref_anno(root: &Id) -> String {
+    format!("refs/annos/{}", hex::encode(root))
+}
+
 impl Store {
     pub const LOCALHOST: &'static str = "localhost";
 
     pub fn new(conf: &Conf) -> Result<Store> {
+        Self::open(&conf.root()?)
+    }
+
+    // create sha256 repository if not exist, true if created
+    pub fn init(root: &str) -> Result<bool> {
+        git::init_bare(root)
+    }
+
+    // store at other path, e.g. for sync
+    pub fn open(root: &str) -> Result<Store> {
+        let root = root.to_string();
+
         let mut res = Store {
-            root: conf.root(),
+            odb: git::odb::Odb::new(&root),
+            batch: git::batch::Batch::new(&root),
+            git_conf: git::config::Config::read(&root),
+            quiet: false,
+            root,
             date: 0,
             zone: "+0000".to_string(),
         };
@@ -123,124 +165,85 @@ impl Store {
     }
 
     pub fn git_show_ref(&self, git_ref: &str) -> Result<Option<Id>> {
-        let git = Command::new("git")
-            .env(ENV_GIT_DIR, &self.root)
-            .arg("show-ref")
-            .arg("--verify")
-            .arg("--hash")
-            .arg(git_ref)
-            .output()
-            .expect("failed to execute git-show-ref");
-
-        // not exist
-        if !git.status.success() { return Ok(None) }
-
-        let out = String::from_utf8_lossy(&git.stdout);
-        let mut res = [0;32];
-        hex::decode_to_slice(&out.trim(), &mut res).unwrap();
-
-        Ok(Some(res))
+        git::refs::read_ref(&self.root, git_ref)
     }
 
     pub fn git_show_ref_all(&self) -> Result<BTreeMap<String, Id>> {
-        let git = Command::new("git")
-            .env(ENV_GIT_DIR, &self.root)
-            .arg("show-ref")
-            .output()
-            .expect("failed to execute git-show-ref");
+        git::refs::list_refs(&self.root)
+    }
 
-        let mut res = BTreeMap::new();
+    pub fn git_update_ref(&self, git_ref: &str, commit: &Id) -> Result<()> {
+        git::refs::update_ref(&self.root, git_ref, commit)
+    }
 
-        // not exist
-        if !git.status.success() { return Ok(res) } // TODO, return error
+    pub fn read_object(&self, oid: &Id) -> Result<(git::Type, Vec<u8>)> {
+        let native = self.odb.read_opt(oid);
 
-        for ln in str::from_utf8(&git.stdout).unwrap().lines() {
-            let mut i = ln.split(' ');
-            let mut id = [0;32];
-            hex::decode_to_slice(&i.next().unwrap(), &mut id).unwrap();
-            let name = i.next().unwrap();
+        if let Ok(Some(x)) = native { return Ok(x) }
 
-            res.insert(name.into(), id);
+        match self.batch.read(oid) {
+            Ok(Some(x)) => Ok(x),
+            Ok(None) => err(&format!("object {} not found", hex::encode(oid))),
+            Err(e) => {
+                debug!("read_object: git cat-file fail, {}", e);
+                match native {
+                    Err(e1) => Err(e1),
+                    _ => err(&format!("object {} not found", hex::encode(oid))),
+                }
+            },
         }
+    }
 
-        Ok(res)
+    // all loose & packed object
+    pub fn object_list(&self) -> Result<Vec<Id>> {
+        self.odb.list()
     }
 
-    pub fn git_update_ref(&self, git_ref: &str, commit: &Id) -> Result<()> {
-        let git = Command::new("git")
-            .env(ENV_GIT_DIR, &self.root)
-            .arg("update-ref")
-            .arg(git_ref)
-            .arg(&hex::encode(commit))
-            .output()
-            .expect("failed to execute git-update-ref");
-
-        // not exist
-        if !git.status.success() {
-            return err("git update-ref fail")
+    // bulk check, result in same order as oids
+    pub fn objects_exist(&self, oids: &[Id]) -> Result<Vec<bool>> {
+        let mut res = vec![];
+        let mut miss = vec![];
+
+        for (i, oid) in oids.iter().enumerate() {
+            let found = self.odb.contains(oid)?;
+            if !found { miss.push(i); }
+            res.push(found);
         }
 
-        Ok(())
+        if miss.is_empty() { return Ok(res) }
+
+        let ids: Vec<Id> = miss.iter().map(|i| oids[*i]).collect();
+        match self.batch.exists(&ids) {
+            Ok(found) => {
+                for (i, f) in miss.into_iter().zip(found) { res[i] = f; }
+            },
+            Err(e) => debug!("objects_exist: git cat-file fail, {}", e),
+        }
+
+        Ok(res)
     }
 
     fn git_cat_file(&self, tp: &str, oid: &Id) -> Result<Vec<u8>> {
-        let git = Command::new("git")
-            .env(ENV_GIT_DIR, &self.root)
-            .arg("cat-file")
-            .arg(tp)
-            .arg(&hex::encode(oid))
-            .output()
-            .expect("failed to execute git-cat-file");
-
-        // not exist
-        if !git.status.success() {
-            return err("git update-ref fail")
+        let (otype, raw) = self.read_object(oid)?;
+
+        if otype.str() != tp {
+            return err(&format!("object {} is {}, not {}",
+                                hex::encode(oid), otype.str(), tp))
         }
 
-        Ok(git.stdout)
+        Ok(raw)
     }
 
     //
     pub fn read_commit(&self, oid: &Id) -> Result<git::Commit> {
         let raw = self.git_cat_file("commit", oid)?;
-        let buf = str::from_utf8(&raw).unwrap();
-
-        let mut slice = &buf[..];
-        let mut ln: &str;
-        let mut parent: Vec<Id> = vec![];
-        let mut tree: Id = [0;32];
+        let res = git::Commit::decode(&raw)?;
 
-        loop {
-            let t = slice.find('\n');
-
-            ln = "";
-            if let Some(n) = t {
-                ln = &slice[..n+1];
-                slice = &slice[n+1..];
-            }
-
-            let ln1 = ln.trim();
-
-            debug!("read_commit: line = {}", ln1);
-            if ln1.starts_with("tree ") {
-                tree = util::to_id(&hex::decode(ln1[5..].trim()).unwrap());
-                debug!("read_commit: tree = {}", hex::encode(&tree));
-            }
-            else if ln1.starts_with("parent ") {
-                let pid = util::to_id(&hex::decode(ln1[6..].trim()).unwrap());
-                debug!("read_commit: parent = {}", hex::encode(&pid));
-                parent.push(pid);
-            }
-            else if ln1.is_empty() {
-                break
-            }
-        }
+        debug!("read_commit: tree = {}, parent = {:?}",
+               hex::encode(res.tree),
+               res.parent.iter().map(hex::encode).collect::<Vec<_>>());
 
-        Ok(git::Commit {
-            parent,
-            tree,
-            comment: slice.to_string()
-        })
+        Ok(res)
     }
 
     pub fn read_commit_anno(&self, oid: &Id, full: bool) -> Result<Anno> {
@@ -253,143 +256,26 @@ impl Store {
     pub fn read_tree(&self, oid: &Id) -> Result<git::Tree> {
         let raw = self.git_cat_file("tree", &oid)?;
 
-        let mut idx0 = 0;
-        let mut idx1 = 0;
-
-        let next_byte_match = |b: u8, idx: &mut usize| -> bool {
-            while *idx < raw.len() {
-                if raw[*idx] == b { return true; }
-                *idx += 1;
-            }
-
-            false
-        };
-
-        let mut res = BTreeSet::new();
-
-        loop {
-            if !next_byte_match(b' ', &mut idx1) { break; }
-
-            debug!("mode: raw = {:?}, idx0 {}, idx1 {}", raw, idx0, idx1);
-            let mode = str::from_utf8(&raw[idx0..idx1]).unwrap();
-            idx0 = idx1 + 1;
-            idx1 = idx0;
-
-            if !next_byte_match(0, &mut idx1) { break; }
-            debug!("name: raw = {:?}, idx0 {}, idx1 {}", raw, idx0, idx1);
-            let name = str::from_utf8(&raw[idx0 .. idx1]).unwrap();
-
-            idx0 = idx1 + 1;
-            idx1 = idx0 + OID_LEN;
-
-            let id = util::to_id(&raw[idx0 .. idx1]);
-
-            let mode_i = i32::from_str_radix(&mode, 8).unwrap();
-
-            res.insert(git::TreeEntry { mode: ObjType::from_mode(mode_i),
-                                        name: name.to_string(),
-                                        oid: id });
+        git::decode_tree(&raw)
+    }
 
-            idx0 = idx1;
-        }
 
-        Ok(res)
+    pub fn has_object(&self, oid: &Id) -> Result<bool> {
+        Ok(self.objects_exist(&[*oid])?[0])
     }
 
-
     pub fn git_hash_object(&self, tp: git::Type, obj: &[u8]) -> Result<Id> {
-        let mut git = Command::new("git")
-            .env(ENV_GIT_DIR, &self.root)
-            .arg("hash-object")
-            .arg("-t")
-            .arg(tp.str())
-            .arg("--stdin")
-            .arg("-w")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .expect("failed to execute git-hash-object");
-
-        {
-            let mut stdin = git.stdin.take().unwrap();
-
-            stdin.write_all(obj)?;
-        }
-
-        if !git.wait()?.success() {
-            debug!("tp: {}, obj: {:?}", tp.str(), obj);
-
-            let mut stderr = git.stderr.take().unwrap();
-            let mut msg = String::new();
-            stderr.read_to_string(&mut msg)?;
-
-            warn!("git_hash_object fail: {}", &msg);
-
-            return Err(err_simple(&msg))
-        }
+        let res = self.odb.write(tp, obj)?;
 
-        // close stdin
-        let mut stdout = git.stdout.take().unwrap();
-
-
-        let mut out = String::new();
-        stdout.read_to_string(&mut out)?;
-        let out1 = out.trim();
-
-        assert_eq!(out1.len(), OID_LEN * 2);
-
-        let mut res = [0;32];
-        hex::decode_to_slice(out1, &mut res).unwrap();
-
-        debug!("hash object {}", out1);
+        debug!("hash object {}", hex::encode(res));
 
         Ok(res)
     }
 
     fn write_tree(&self, tree: &git::Tree) -> Result<Id> {
-        /* format
-        tree <size>\0
-        <mode> <name>\0<oid>
-        <mode> <name>\0<oid>
-        ...
-         */
-        let mut git = Command::new("git")
-            .env(ENV_GIT_DIR, &self.root)
-            .arg("hash-object")
-            .arg("-t")
-            .arg("tree")
-            .arg("--stdin")
-            .arg("-w")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .expect("failed to execute git-hash-object");
-
-        {
-            let mut stdin = git.stdin.take().unwrap();
-
-            for entry in tree {
-                stdin.write_fmt(format_args!("{:o} {}\0", entry.mode.mode(), entry.name))?;
-                stdin.write_all(&entry.oid)?;
-            }
-        }
-
-        // close stdin
-
-        let mut stdout = git.stdout.take().unwrap();
+        let res = self.odb.write(git::Type::Tree, &git::encode_tree(tree))?;
 
-        let mut out = String::new();
-        stdout.read_to_string(&mut out)?;
-        let out1 = out.trim();
-
-        assert_eq!(out1.len(), OID_LEN * 2);
-
-        let mut res = [0;32];
-        hex::decode_to_slice(out1, &mut res).unwrap();
-
-        debug!("write tree {}", out1);
+        debug!("write tree {}", hex::encode(res));
 
         Ok(res)
     }
@@ -399,61 +285,102 @@ impl Store {
                    time: u64, msg: &str) -> Result<Id> {
         let date = format!("{} {}", time, self.zone);
 
-        let mut pid_list = vec![];
-
-        for parent in parents.iter() {
-            pid_list.push("-p".to_string());
-            pid_list.push(hex::encode(parent));
-        }
+        let author = git::config::Ident::get(&self.git_conf, "AUTHOR")?;
+        let committer = git::config::Ident::get(&self.git_conf, "COMMITTER")?;
 
-        let git = Command::new("git")
-            .env(ENV_GIT_DIR, &self.root)
-            .env(ENV_GIT_AUTHOR_DATE, &date)
-            .env(ENV_GIT_COMMITTER_DATE, &date)
-            .arg("commit-tree")
-            .args(&pid_list)
-            .arg("-m")
-            .arg(msg)
-            .arg(hex::encode(tree))
-            .output()
-            .expect("failed to execute git-commit-tree");
-
-        let out = String::from_utf8_lossy(&git.stdout);
-
-        let mut res = [0;32];
-
-        hex::decode_to_slice(&out.trim(), &mut res).unwrap();
+        let commit = git::Commit {
+            parent: parents.to_vec(),
+            tree: *tree,
+            author: author.line(&date),
+            committer: committer.line(&date),
+            comment: msg.to_string(),
+        };
 
-        Ok(res)
+        self.odb.write(git::Type::Commit, &commit.encode())
     }
 
+    // commit object only, ref updated by update_anno_ref once journaled
     fn commit_anno(&self, anno: &Anno) -> Result<Id> {
-        let mut time = self.date;
-
-        if let Some(cv::Value::Integer(_i)) = anno.data.get("mtime") {
-            time = (*_i / 1000) as u64;
-        };
+        let time = anno.get_mtime().map_or(self.date, |t| t / 1000);
+        self.commit_anno_at(anno, time)
+    }
 
+    fn commit_anno_at(&self, anno: &Anno, time: u64) -> Result<Id> {
         let yaml = anno.gen_yaml()?;
         let msg = yaml.trim_start_matches('-').trim();
 
-        let oid = self.commit_tree(&anno.pid, &anno.fid, time, msg)?;
+        self.commit_tree(&anno.pid, &anno.fid, time, msg)
+    }
+
+    // tip -> root, of refs/annos
+    fn anno_tips(&self) -> Result<BTreeMap<Id, Id>> {
+        let mut res = BTreeMap::new();
+
+        for (name, tip) in self.git_show_ref_all()? {
+            if let Some(root) = name.strip_prefix("refs/annos/") {
+                res.insert(tip, git::parse_hex(root)?);
+            }
+        }
+
+        Ok(res)
+    }
 
-        // TODO, update ref for anno
+    // oid is new commit on pid, or a root; walk to root only when pid is
+    // not a tip, e.g. old anno without ref
+    fn update_anno_ref(&self, tips: &BTreeMap<Id, Id>, pid: Option<&Id>, oid: &Id)
+                       -> Result<()> {
+        let root = match pid {
+            Some(pid) => match tips.get(pid) {
+                Some(root) => *root,
+                None => self.anno_root(pid)?,
+            },
+            None => *oid,
+        };
 
-        Ok(oid)
+        self.git_update_ref(&ref_anno(&root), oid)
     }
 
     pub fn commit(&mut self, manifest: &mut Manifest) -> Result<CommitResult> {
+        self.commit_only(manifest, None)
+    }
+
+    // commit annos of `only` rpaths, or all when None
+    pub fn commit_only(&mut self, manifest: &mut Manifest, only: Option<&[String]>)
+                       -> Result<CommitResult> {
         self.update_time()?;
 
-        let mut res = CommitResult { obj_list: vec![], oid: None };
+        let mut res = CommitResult { obj_list: vec![], oid: None, tree: None,
+                                     annos: vec![] };
+
+        let journal = Journal::new(&manifest.mdir);
+        for e in self.pending(&journal)? {
+            say!(self, "--> {} (resume)", e.rpath);
+            res.obj_list.push(e.aid);
+            res.annos.push((e.rpath, e.aid, true));
+        }
+
+        // nothing committed if any is invalid
+        for (name, anno) in manifest.anno_map.iter()
+            .filter(|(name, _)| only.is_none_or(|o| o.contains(name))) {
+            if anno.status()? == St::Ready { continue }
+
+            if let Err(e) = manifest.schema.validate(&anno.data) {
+                return err(&format!("{} fail schema check\n{}", name, e))
+            }
+        }
+
+        let tips = self.anno_tips()?;
 
-        for (name, anno) in manifest.anno_map.iter_mut() {
-            println!("--> {}", name);
+        let selected = manifest.anno_map.iter_mut()
+            .filter(|(name, _)| only.is_none_or(|o| o.contains(name)));
+
+        for (name, anno) in selected {
+            say!(self, "--> {}", name);
 
             //
             let func = |st: St, an: &mut Anno| -> Result<()> {
+                let old = (an.fid, an.anno_hash, an.pid.clone());
+
                 if st == St::MFile {
                     an.fid = self.import(an.get_file_path()
                                              .to_str()
@@ -463,11 +390,22 @@ impl Store {
                 // create commit
                 let pid = self.commit_anno(an)?;
 
-                println!("    commit {} {}",
+                say!(self, "    commit {} {}",
                          &util::to_zbase32(&pid)[..8],
                          &hex::encode(&pid));
 
-                res.obj_list.push(pid.clone());
+                // same commit, if resume after crash before save
+                if !res.obj_list.contains(&pid) {
+                    journal.append(&journal::Entry {
+                        aid: pid, fid: old.0, anno_hash: old.1, pid: old.2.clone(),
+                        rpath: name.clone() })?;
+
+                    res.obj_list.push(pid);
+                    res.annos.push((name.clone(), pid, false));
+                }
+
+                // after journaled, so rollback always know the old tip
+                self.update_anno_ref(&tips, old.2.first(), &pid)?;
 
                 an.pid.clear();
                 an.pid.push(pid);
@@ -480,6 +418,84 @@ impl Store {
 
         if res.obj_list.is_empty() { return Ok(res) }
 
+        self.commit_cset(&mut res)?;
+
+        journal.clear()?;
+
+        Ok(res)
+    }
+
+    // tombstone on each committed anno, its chain end here; rpath, tip.
+    // journaled as commit_only, tombstone of interrupted one is reused
+    pub fn commit_removed(&mut self, mdir: &str, annos: &[(String, Id)])
+                          -> Result<CommitResult> {
+        self.update_time()?;
+
+        let mut res = CommitResult { obj_list: vec![], oid: None, tree: None,
+                                     annos: vec![] };
+
+        let journal = Journal::new(mdir);
+        for e in self.pending(&journal)? {
+            say!(self, "--> {} (resume)", e.rpath);
+            res.obj_list.push(e.aid);
+            res.annos.push((e.rpath, e.aid, true));
+        }
+
+        let tips = self.anno_tips()?;
+
+        for (name, pid) in annos {
+            say!(self, "--> {} (removed)", name);
+
+            let mut an = self.read_commit_anno(pid, true)?;
+            if an.is_removed() {
+                return err(&format!("{} is already removed", name))
+            }
+
+            // pending one resumed above, or already in changeset
+            if self.removed_on(pid)?.is_some() { continue }
+
+            let old_hash = an.anno_hash;
+
+            an.pid = vec![*pid];
+            an.data.insert(anno::REMOVED.to_string(), cv::Value::Bool(true));
+
+            let oid = self.commit_anno_at(&an, self.date)?;
+
+            journal.append(&journal::Entry {
+                aid: oid, fid: an.fid, anno_hash: old_hash, pid: an.pid.clone(),
+                rpath: name.clone() })?;
+
+            self.update_anno_ref(&tips, Some(pid), &oid)?;
+
+            say!(self, "    commit {} {}",
+                     &util::to_zbase32(&oid)[..8],
+                     &hex::encode(oid));
+
+            res.obj_list.push(oid);
+            res.annos.push((name.clone(), oid, false));
+        }
+
+        if res.obj_list.is_empty() { return Ok(res) }
+
+        self.commit_cset(&mut res)?;
+
+        journal.clear()?;
+
+        Ok(res)
+    }
+
+    // tombstone already committed on aid
+    fn removed_on(&self, aid: &Id) -> Result<Option<Id>> {
+        let tip = self.anno_tip(aid)?;
+        if tip == *aid { return Ok(None) }
+
+        let an = self.read_commit_anno(&tip, true)?;
+
+        Ok(Some(tip).filter(|_| an.is_removed() && an.pid.first() == Some(aid)))
+    }
+
+    // changeset of res.obj_list on localhost tip
+    fn commit_cset(&mut self, res: &mut CommitResult) -> Result<()> {
         // create tree for new commit
         let len = min_uniq_len(&res.obj_list);
 
@@ -490,21 +506,17 @@ impl Store {
         }
 
         // add .gitmodules
-        let mut lns = vec![];
-        for entry in &tree {
-            lns.push(format!("[submodule \"{}\"]\npath={}\nurl=./\n",
-                                    entry.name, entry.name));
-        }
-        let gitmodules = lns.join("");
+        let gitmodules = gen_gitmodules(&tree);
         tree.insert(git::TreeEntry { name: ".gitmodules".into(),
                                      oid: self.git_hash_object(git::Type::blob(),
                                                                gitmodules.as_bytes())?,
                                      mode: git::Type::blob() });
 
         let tid = self.write_tree(&tree)?;
+        res.tree = Some(tid);
 
-        println!("---");
-        println!("tree   {} {}",
+        say!(self, "---");
+        say!(self, "tree   {} {}",
                  &util::to_zbase32(&tid)[..8],
                  &hex::encode(tid));
 
@@ -519,75 +531,39 @@ impl Store {
 
         self.git_update_ref(&ref_remote(Self::LOCALHOST), &cset_commit)?;
 
-        println!("commit {} {}",
+        say!(self, "commit {} {}",
                  &util::to_zbase32(&cset_commit)[..8],
                  &hex::encode(&cset_commit));
 
         res.oid = Some(cset_commit);
 
-
-        Ok(res)
+        Ok(())
     }
 
-    pub fn spawn_bup_join(&self, id: &Id) -> Result<process::Child> {
-        let bup = Command::new(BUP_CMD)
-            .env(ENV_BUP_DIR, self.root.clone())
-            .env("LC_ALL", "C")
-            .arg("join")
-            .arg(hex::encode(id))
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()?;
-
-        Ok(bup)
+    // write content of fid, return size
+    pub fn join<W: Write>(&self, id: &Id, out: &mut W) -> Result<u64> {
+        hashsplit::join(id, out, &mut |oid| self.read_object(oid))
     }
 
-    pub fn import(&self, path: &str) -> Result<Id> {
-        let mut bup = Command::new(BUP_CMD)
-            .env(ENV_BUP_DIR, self.root.clone())
-            .env(ENV_BUP_FORCE_TTY, "3")
-            .env("LC_ALL", "C")
-            .arg("split")
-            .arg("-t")
-            .arg(path)
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .expect("Failed to start bup process");
-
-        let mut buf = vec![];
-        let io2 = bup.stderr.take().unwrap();
-        let mut rdr2 = BufReader::new(io2);
+    // join in a thread, content read from returned pipe
+    pub fn spawn_join(&self, id: &Id)
+                      -> Result<(io::PipeReader, thread::JoinHandle<Result<u64>>)> {
+        let (rdr, mut wtr) = io::pipe()?;
+        let store = self.clone();
+        let id = *id;
 
-        loop {
-            buf.clear();
+        let handle = thread::spawn(move || store.join(&id, &mut wtr));
 
-            rdr2.read_until(b'\r', &mut buf)?;
-
-            if buf.is_empty() { break }
-
-            let ln = String::from_utf8_lossy(&buf);
-
-            eprint!("\r{}", ln);
-            io::stderr().flush()?;
-        }
-
-        // should wait to avoid zombie
-        bup.wait()?;
-
-        let mut out = String::new();
-        let mut io1 = bup.stdout.take().unwrap();
-        io1.read_to_string(&mut out)?;
+        Ok((rdr, handle))
+    }
 
-        let out1 = out.trim();
-        assert_eq!(out1.len(), 2 * OID_LEN);
+    pub fn import(&self, path: &str) -> Result<Id> {
+        let mut file = BufReader::new(File::open(path)?);
 
-        let mut res = [0;32];
-        hex::decode_to_slice(out1, &mut res).unwrap();
+        let res = hashsplit::split(&mut file,
+                                   &mut |tp, data| self.odb.write(tp, data))?;
 
-        println!("    tree   {} {}", &util::to_zbase32(&res)[..8], out1);
+        say!(self, "    tree   {} {}", &util::to_zbase32(&res)[..8], hex::encode(res));
 
         Ok(res)
     }
@@ -652,7 +628,322 @@ impl Store {
     }
 }
 
+impl Store {
+    // host names, from "refs/remotes/<name>/localhost"
+    pub fn cset_all(&self) -> Result<Vec<String>> {
+        let res = self.git_show_ref_all()?.keys()
+            .filter_map(|x| x.strip_prefix("refs/remotes/")
+                        .and_then(|x| x.strip_suffix("/localhost"))
+                        .map(|x| x.to_string()))
+            .collect();
+
+        Ok(res)
+    }
+
+    // all anno commits in changesets of every host
+    pub fn anno_all(&self) -> Result<BTreeSet<Id>> {
+        let mut res = BTreeSet::new();
+
+        for cset in self.cset_all()? {
+            let tip = match self.git_show_ref(&ref_remote(&cset))? {
+                Some(x) => x,
+                None => continue,
+            };
+
+            for (_, tree) in self.walk(&tip, None)? {
+                res.extend(tree.into_iter()
+                           .filter(|te| te.mode == git::Type::Commit)
+                           .map(|te| te.oid));
+            }
+        }
+
+        Ok(res)
+    }
+
+    // stable identity of anno, first commit following first parent
+    pub fn anno_root(&self, aid: &Id) -> Result<Id> {
+        let mut id = *aid;
+
+        loop {
+            match self.read_commit(&id)?.parent.first() {
+                Some(pid) => id = *pid,
+                None => return Ok(id),
+            }
+        }
+    }
+
+    // latest commit of anno, the ref may not exist for old anno
+    pub fn anno_tip(&self, aid: &Id) -> Result<Id> {
+        let root = self.anno_root(aid)?;
+
+        Ok(self.git_show_ref(&ref_anno(&root))?.unwrap_or(*aid))
+    }
+
+    // history from tip, newest first
+    pub fn anno_log(&self, tip: &Id) -> Result<Vec<(Id, Anno)>> {
+        let mut res = vec![];
+        let mut seen = BTreeSet::new();
+        let mut remain = vec![*tip];
+
+        while let Some(id) = remain.pop() {
+            if !seen.insert(id) { continue }
+
+            let anno = self.read_commit_anno(&id, true)?;
+            remain.extend(anno.pid.iter().rev().cloned());
+            res.push((id, anno));
+        }
+
+        Ok(res)
+    }
+
+    // include aid itself
+    pub fn anno_ancestors(&self, aid: &Id) -> Result<BTreeSet<Id>> {
+        let mut res = BTreeSet::new();
+        let mut remain = vec![*aid];
+
+        while let Some(id) = remain.pop() {
+            if !res.insert(id) { continue }
+            remain.extend(self.read_commit(&id)?.parent);
+        }
+
+        Ok(res)
+    }
+
+    // nearest common ancestor of two anno commits
+    pub fn anno_merge_base(&self, a: &Id, b: &Id) -> Result<Option<Id>> {
+        let ancestors = self.anno_ancestors(a)?;
+
+        let mut seen = BTreeSet::new();
+        let mut remain = VecDeque::from(vec![*b]);
+
+        while let Some(id) = remain.pop_front() {
+            if ancestors.contains(&id) { return Ok(Some(id)) }
+            if !seen.insert(id) { continue }
+
+            remain.extend(self.read_commit(&id)?.parent);
+        }
+
+        Ok(None)
+    }
+
+    // root -> tips of anno, from changesets of every host
+    pub fn anno_heads(&self) -> Result<BTreeMap<Id, Vec<Id>>> {
+        let all = self.anno_all()?;
+        let mut roots: BTreeMap<Id, Id> = BTreeMap::new();
+        let mut parents = BTreeSet::new();
+
+        for aid in all.iter() {
+            let mut chain = vec![];
+            let mut id = *aid;
+
+            let root = loop {
+                if let Some(r) = roots.get(&id) { break *r }
+
+                let commit = self.read_commit(&id)?;
+                parents.extend(commit.parent.iter().cloned());
+                chain.push(id);
+
+                match commit.parent.first() {
+                    Some(pid) => id = *pid,
+                    None => break id,
+                }
+            };
+
+            for x in chain { roots.insert(x, root); }
+        }
+
+        let mut res: BTreeMap<Id, Vec<Id>> = BTreeMap::new();
+        for aid in all.into_iter().filter(|x| !parents.contains(x)) {
+            res.entry(roots[&aid]).or_default().push(aid);
+        }
+
+        Ok(res)
+    }
+
+    // local tip, when aid & local history of same anno diverged
+    pub fn anno_diverged(&self, aid: &Id) -> Result<Option<Id>> {
+        let root = self.anno_root(aid)?;
+
+        let tip = match self.git_show_ref(&ref_anno(&root))? {
+            Some(x) if x != *aid => x,
+            _ => return Ok(None),
+        };
+
+        if self.anno_ancestors(aid)?.contains(&tip) ||
+            self.anno_ancestors(&tip)?.contains(aid) {
+            return Ok(None)
+        }
+
+        Ok(Some(tip))
+    }
+
+    // anno commits of interrupted commit, not in any changeset yet
+    fn pending(&self, journal: &Journal) -> Result<Vec<journal::Entry>> {
+        let entries = journal.load()?;
+        if entries.is_empty() { return Ok(entries) }
+
+        // interrupted after changeset ref updated
+        let done: BTreeSet<Id> = match self.git_show_ref(&ref_remote(Self::LOCALHOST))? {
+            Some(tip) => self.read_tree(&self.read_commit(&tip)?.tree)?
+                .into_iter().map(|te| te.oid).collect(),
+            None => BTreeSet::new(),
+        };
+
+        let aids: Vec<Id> = entries.iter().map(|e| e.aid).collect();
+        let exist = self.objects_exist(&aids)?;
+
+        let res: Vec<journal::Entry> = entries.into_iter().zip(exist)
+            .filter(|(e, found)| {
+                if !found { say!(self, "--> {} lost, skip", e.rpath) }
+                *found && !done.contains(&e.aid)
+            })
+            .map(|(e, _)| e)
+            .collect();
+
+        // rewrite, drop partial line
+        journal.clear()?;
+        for e in res.iter() { journal.append(e)?; }
+
+        Ok(res)
+    }
+
+    // undo interrupted commit, sidecar & anno ref back to old state
+    pub fn rollback(&self, manifest: &mut Manifest) -> Result<usize> {
+        let journal = Journal::new(&manifest.mdir);
+        let entries = self.pending(&journal)?;
+
+        for e in entries.iter().rev() {
+            say!(self, "--> {} rollback {}", e.rpath, &util::to_zbase32(&e.aid)[..8]);
+
+            if let Some(anno) = manifest.anno_map.get_mut(&e.rpath) {
+                if anno.pid.first() == Some(&e.aid) {
+                    // file imported by that commit, its .meta now match the
+                    // new content, so clear fid to import again as MFile
+                    anno.fid = if anno.fid == e.fid { e.fid } else { [0; 32] };
+                    anno.anno_hash = e.anno_hash;
+                    anno.pid = e.pid.clone();
+                    anno.save()?;
+                }
+            }
+
+            let root = self.anno_root(&e.aid)?;
+            if self.git_show_ref(&ref_anno(&root))? == Some(e.aid) {
+                match e.pid.first() {
+                    Some(pid) => self.git_update_ref(&ref_anno(&root), pid)?,
+                    None => git::refs::delete_ref(&self.root, &ref_anno(&root))?,
+                }
+            }
+        }
+
+        journal.clear()?;
+
+        Ok(entries.len())
+    }
+
+    // full id or unique prefix of anno, in zbase32 or hex
+    pub fn resolve_anno(&self, s: &str) -> Result<Id> {
+        if s.len() == 52 { return Ok(util::zbase32_to_id(s)) }
+        if s.len() == 64 { return git::parse_hex(s) }
+
+        let found: Vec<Id> = self.anno_all()?.into_iter()
+            .filter(|id| util::to_zbase32(id).starts_with(s) ||
+                    hex::encode(id).starts_with(s))
+            .collect();
+
+        match found.len() {
+            0 => err(&format!("no anno match '{}'", s)),
+            1 => Ok(found[0]),
+            n => err(&format!("'{}' is ambiguous, {} annos match", s, n)),
+        }
+    }
+
+    fn join_file(&self, id: &Id, path: &Path) -> Result<u64> {
+        let mut file = io::BufWriter::new(File::create(path)?);
+        let size = self.join(id, &mut file)?;
+        file.flush()?;
+
+        Ok(size)
+    }
+
+    // write content of anno to path, restore recorded mtime
+    pub fn checkout(&self, anno: &Anno, path: &Path) -> Result<u64> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let name = path.file_name()
+            .ok_or_else(|| err_simple(&format!("invalid path {:?}", path)))?;
+        let tmp = path.with_file_name(
+            format!(".{}.nep-tmp", name.to_string_lossy()));
+
+        let size = match self.join_file(&anno.fid, &tmp) {
+            Ok(x) => x,
+            Err(e) => {
+                fs::remove_file(&tmp).ok();
+                return Err(e)
+            }
+        };
+
+        if let Some(mt) = anno.get_mtime() {
+            let ft = FileTime::from_unix_time((mt / 1000) as i64,
+                                              (mt % 1000) as u32 * 1000000);
+            filetime::set_file_mtime(&tmp, ft)?;
+        }
+
+        fs::rename(&tmp, path)?;
+
+        Ok(size)
+    }
+}
+
 #[cfg(test)]
 mod test {
     // TODO, test walk
+
+    use super::*;
+
+    #[test]
+    fn test_rollback() {
+        let dir = std::env::temp_dir().join(format!("nep-test-rollback-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let root = dir.join("store").to_string_lossy().to_string();
+        let mdir = dir.join(crate::proj::MANIFEST).to_string_lossy().to_string();
+        let file = dir.join("a.txt");
+
+        Store::init(&root).unwrap();
+        fs::create_dir_all(&mdir).unwrap();
+        fs::write(&file, "old").unwrap();
+
+        let mut store = Store::open(&root).unwrap();
+        store.quiet = true;
+
+        let mut manifest = Manifest::new(&mdir).unwrap();
+        manifest.add("a.txt").unwrap();
+        store.commit(&mut manifest).unwrap();
+
+        let cset = store.git_show_ref(&ref_remote(Store::LOCALHOST)).unwrap().unwrap();
+        let anno = &manifest.anno_map["a.txt"];
+        let old = (anno.fid, anno.anno_hash, anno.pid.clone());
+
+        fs::write(&file, "new").unwrap();
+        let ft = FileTime::from_unix_time(1600000000, 0);
+        filetime::set_file_times(&file, ft, ft).unwrap();
+
+        // interrupted after anno saved, before changeset ref updated
+        let res = store.commit(&mut manifest).unwrap();
+        Journal::new(&mdir).append(&journal::Entry {
+            aid: res.annos[0].1, fid: old.0, anno_hash: old.1, pid: old.2,
+            rpath: "a.txt".to_string() }).unwrap();
+        store.git_update_ref(&ref_remote(Store::LOCALHOST), &cset).unwrap();
+
+        assert_eq!(store.rollback(&mut manifest).unwrap(), 1);
+        assert!(manifest.anno_map["a.txt"].status().unwrap() == St::MFile);
+
+        drop(manifest);
+        let manifest = Manifest::new(&mdir).unwrap();
+        assert!(manifest.anno_map["a.txt"].status().unwrap() == St::MFile);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
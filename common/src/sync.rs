@@ -0,0 +1,171 @@
+// copy changesets between stores, e.g. a second repo on usb disk
+
+use std::collections::BTreeSet;
+
+use log::debug;
+
+use crate::error::*;
+use crate::git;
+use crate::store::{self, Store};
+use crate::util::{self, Id};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    UpToDate,
+    // old tip, new tip, object copied
+    Forward(Option<Id>, Id, usize),
+}
+
+// print result of one host, return range of new changesets
+pub fn report(host: &str, res: Result<Outcome>) -> Option<(Option<Id>, Id)> {
+    match res {
+        Ok(Outcome::UpToDate) => println!("{}: up to date", host),
+        Ok(Outcome::Forward(old, tip, n)) => {
+            println!("{}: {} -> {}, {} object", host,
+                     old.map_or("none".to_string(),
+                                |x| util::to_zbase32(&x)[..8].to_string()),
+                     &util::to_zbase32(&tip)[..8], n);
+            return Some((old, tip))
+        },
+        Err(e) => println!("{}: error, {}", host, e),
+    }
+
+    None
+}
+
+fn is_ancestor(store: &Store, a: &Id, b: &Id) -> Result<bool> {
+    let mut seen = BTreeSet::new();
+    let mut remain = vec![*b];
+
+    while let Some(id) = remain.pop() {
+        if id == *a { return Ok(true) }
+        if !seen.insert(id) { continue }
+
+        remain.extend(store.read_commit(&id)?.parent);
+    }
+
+    Ok(false)
+}
+
+// copy oid and everything it reference, skip what dst already has
+//
+// object is written after all its reference, so an existing object
+// in dst means complete, an interrupted copy can simply run again
+fn copy(src: &Store, dst: &Store, oid: &Id) -> Result<usize> {
+    let mut res = 0;
+    let mut done = BTreeSet::new();
+
+    // (oid, reference already pushed)
+    let mut remain = vec![(*oid, false)];
+
+    while let Some((id, expanded)) = remain.pop() {
+        if expanded {
+            let (tp, data) = src.read_object(&id)?;
+
+            if dst.git_hash_object(tp, &data)? != id {
+                return err(&format!("object {} corrupt", hex::encode(id)))
+            }
+
+            res += 1;
+            continue
+        }
+
+        if !done.insert(id) || dst.has_object(&id)? { continue }
+
+        remain.push((id, true));
+
+        let (tp, data) = src.read_object(&id)?;
+
+        let refs: Vec<Id> = match tp {
+            git::Type::Commit => {
+                let c = git::Commit::decode(&data)?;
+                std::iter::once(c.tree).chain(c.parent).collect()
+            },
+            // changeset tree has anno commit as gitlink
+            git::Type::Tree => git::decode_tree(&data)?.into_iter()
+                .map(|te| te.oid).collect(),
+            _ => vec![],
+        };
+
+        for r in refs.into_iter() {
+            if !done.contains(&r) { remain.push((r, false)); }
+        }
+    }
+
+    Ok(res)
+}
+
+// copy changesets at src_ref, then fast-forward dst_ref
+pub fn sync(src: &Store, src_ref: &str, dst: &Store, dst_ref: &str)
+            -> Result<Outcome> {
+    let tip = match src.git_show_ref(src_ref)? {
+        Some(x) => x,
+        None => return err(&format!("{} not found", src_ref)),
+    };
+
+    let old = dst.git_show_ref(dst_ref)?;
+
+    debug!("sync: {} {} -> {} {:?}", src_ref, hex::encode(tip),
+           dst_ref, old.map(hex::encode));
+
+    if old == Some(tip) { return Ok(Outcome::UpToDate) }
+
+    if let Some(old) = old {
+        let forward = src.has_object(&old)? && is_ancestor(src, &old, &tip)?;
+
+        if !forward {
+            // dst is newer, nothing to do
+            if dst.has_object(&tip)? && is_ancestor(dst, &tip, &old)? {
+                return Ok(Outcome::UpToDate)
+            }
+
+            return err(&format!("{} is not fast-forward, {} -> {}", dst_ref,
+                                &util::to_zbase32(&old)[..8],
+                                &util::to_zbase32(&tip)[..8]))
+        }
+    }
+
+    let n = copy(src, dst, &tip)?;
+
+    dst.git_update_ref(dst_ref, &tip)?;
+
+    Ok(Outcome::Forward(old, tip, n))
+}
+
+// changesets of src itself as `host` in dst, changesets of other hosts
+// relayed under same name, except those of dst itself
+pub fn sync_all(src: &Store, dst: &Store, host: &str)
+                -> Result<Vec<(String, Result<Outcome>)>> {
+    let local = store::ref_remote(Store::LOCALHOST);
+    let dst_tip = dst.git_show_ref(&local)?;
+
+    let mut res = vec![];
+
+    for h in src.cset_all()? {
+        if h == Store::LOCALHOST {
+            let dst_ref = store::ref_remote(host);
+            res.push((host.to_string(), sync(src, &local, dst, &dst_ref)));
+            continue
+        }
+
+        // name taken by src itself
+        if h == host { continue }
+
+        let src_ref = store::ref_remote(&h);
+        let tip = match src.git_show_ref(&src_ref)? {
+            Some(x) => x,
+            None => continue,
+        };
+
+        if let Some(t) = dst_tip.as_ref() {
+            if dst.has_object(&tip)? && is_ancestor(dst, &tip, t)? {
+                debug!("sync_all: skip {}, changeset of dst", h);
+                continue
+            }
+        }
+
+        res.push((h.clone(), sync(src, &src_ref, dst, &src_ref)));
+    }
+
+    Ok(res)
+}
@@ -1,11 +1,11 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::path::Path;
 
 use crypto::sha2::Sha256;
 use crypto::digest::Digest;
 
 use std::io;
-use std::io::Read;
+use std::io::{Read, Write};
 
 use serde_json;
 
@@ -175,6 +175,25 @@ pub fn to_id(slice: &[u8]) -> Id {
     res
 }
 
+// write to temp file in same dir, then rename, reader never see partial file
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("invalid path {:?}", path)))?;
+    let tmp = path.with_file_name(format!(".{}.nep-tmp", name.to_string_lossy()));
+
+    let res = (|| {
+        let mut f = File::create(&tmp)?;
+        f.write_all(data)?;
+        f.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+
+    if res.is_err() { fs::remove_file(&tmp).ok(); }
+
+    res
+}
+
 
 #[test]
 fn test_to_zbase32()
@@ -1,20 +1,63 @@
 // nephrite-index
 
+use clap::{Arg, App};
+
 use nephrite4_common::conf;
+use nephrite4_common::output;
 //use serde_json::Value;
 //use serde_json::map::Map;
 
-use nephrite4_query::{error::*, index};
+use nephrite4_query::{db, error::*, index};
+
+fn migrate(conf: &conf::Conf) -> Result<()> {
+    let mut client = db::connect(conf)?;
+
+    let (old, ms) = db::migrate::migrate(&mut client)?;
+
+    for m in ms.iter() {
+        println!("migrate {} {} ... ok", m.version, m.name);
+    }
+
+    match old {
+        Some(v) if ms.is_empty() && v == db::migrate::latest() =>
+            println!("schema up to date, version {}", v),
+        _ => println!("schema version {}", db::migrate::latest()),
+    }
+
+    Ok(())
+}
 
 fn main() -> Result<()> {
     env_logger::init();
 
-    let conf = conf::Conf::read();
+    let matches =
+        App::new("index")
+        .arg(Arg::with_name("migrate")
+             .long("migrate")
+             .help("upgrade database schema, then exit"))
+        .arg(Arg::with_name("json")
+             .long("json")
+             .help("output NDJSON"))
+        .get_matches();
+
+    let conf = conf::Conf::read()?;
+
+    if matches.is_present("migrate") {
+        return migrate(&conf)
+    }
+
     let mut indexer = index::Indexer::new(&conf)?;
+    indexer.json = matches.is_present("json") || output::enabled(&[]);
+    indexer.store.quiet = indexer.json;
 
     let num = indexer.index_cset_all()?;
 
-    println!("index total {} objs", num);
+    if indexer.json {
+        output::emit(serde_json::json!({"type": "index", "total": num}));
+    }
+    else {
+        println!("index total {} objs", num);
+    }
 
     Ok(())
 }
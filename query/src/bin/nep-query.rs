@@ -4,6 +4,7 @@ use clap::{Arg, App, AppSettings};
 
 use nephrite4_common::conf;
 use nephrite4_common::util;
+use nephrite4_common::output;
 
 use util::Id;
 
@@ -22,7 +23,7 @@ fn main() {
              .short("n")
              .long("num")
              .takes_value(true)
-             .help("limit number of record, default 10"))
+             .help("limit number of record, default query.limit in config or 10"))
         .arg(Arg::with_name("all")
              .long("all")
              .help("search for all record, include obsolete"))
@@ -37,33 +38,65 @@ fn main() {
              .long("save")
              .help("save search result to table")
              .takes_value(true))
+        .arg(Arg::with_name("json")
+             .long("json")
+             .help("output NDJSON"))
         .setting(AppSettings::TrailingVarArg)
         .arg(Arg::from_usage("<patterns>... 'search patterns'"))
         .get_matches();
 
-    let num = matches.value_of("num").unwrap_or("10").parse().unwrap_or(10);
     let all = matches.is_present("all");
+    let json = matches.is_present("json") || output::enabled(&[]);
     let qs: Vec<_> = matches.values_of("patterns").unwrap().collect();
 
     //println!("num {}, all {}, patt {:?}", num, all, qs);
 
     // db init
-    let conf = conf::Conf::read();
+    let conf = match conf::Conf::read() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let num = match matches.value_of("num") {
+        Some(n) => n.parse().unwrap_or(10),
+        None => conf.query_limit().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+    };
 
     //let store = store::Store::new(&conf);
 
     // ensure
-    let mut client = db::client(&conf).unwrap();
+    let mut client = match db::client(&conf) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
 
     let patt = qs.iter().map(|e| search::to_search(e)).collect::<Vec<_>>();
 
-    let res = search::search(&mut client, &patt, all, num).unwrap();
+    let res = search::search(&mut client, &patt, all, num as i64).unwrap();
 
     let mut fids: Vec<Id> = vec![];
 
     for (id, fid) in res.into_iter() {
         let attr = search::get_attr(&mut client, &id).unwrap();
 
+        if json {
+            output::emit(serde_json::json!({
+                "type": "result", "id": output::id(&id), "fid": output::id(&fid),
+                "attr": attr}));
+
+            fids.push(fid);
+            continue;
+        }
+
         print!("{} {} ",
                  &util::to_zbase32(&fid)[..7],
                &util::to_zbase32(&id)[..7]);
@@ -91,6 +124,13 @@ fn main() {
 
     search::sel_save(&mut client, &sel_name, &fids, clear, append).unwrap();
 
+    if json {
+        output::emit(serde_json::json!({
+            "type": "selection", "name": sel_name, "count": fids.len(),
+            "append": append}));
+        return
+    }
+
     println!("\n{} record {} to '{}'",
              fids.len(),
              if append { "appended" } else { "saved" },
@@ -20,7 +20,7 @@ fn cat(p: &str) -> Result<Child> {
 fn main() -> Result<()> {
     env_logger::init();
 
-    let conf = conf::Conf::read();
+    let conf = conf::Conf::read()?;
 
     let tika = tika::Tika::new(&conf)?;
 
@@ -1,11 +1,36 @@
 pub mod types;
 pub mod search;
+pub mod migrate;
 
-use nephrite4_common::conf;
+use nephrite4_common::conf::{self, DbTls};
 
 use crate::error::*;
-use postgres::{Client, NoTls};
+use postgres::{Client, Config, NoTls};
+use postgres::config::SslMode;
+use postgres_native_tls::MakeTlsConnector;
+
+pub fn connect_url(url: &str, tls: DbTls) -> Result<Client> {
+    let mut config: Config = url.parse()?;
+
+    let mode = match tls {
+        DbTls::Disable => return Ok(config.ssl_mode(SslMode::Disable).connect(NoTls)?),
+        DbTls::Prefer => SslMode::Prefer,
+        DbTls::Require => SslMode::Require,
+    };
+
+    let connector = native_tls::TlsConnector::new()
+        .map_err(|e| err_simple(&format!("tls: {}", e)))?;
+
+    Ok(config.ssl_mode(mode).connect(MakeTlsConnector::new(connector))?)
+}
+
+// connect without schema check, for migrate
+pub fn connect(conf: &conf::Conf) -> Result<Client> {
+    connect_url(&conf.db_url()?, conf.db_tls()?)
+}
 
 pub fn client(conf: &conf::Conf) -> Result<Client> {
-    Ok(Client::connect(&conf.db_url(), NoTls)?)
+    let mut client = connect(conf)?;
+    migrate::check(&mut client)?;
+    Ok(client)
 }
@@ -0,0 +1,123 @@
+// schema migrations, compiled in, applied in order once each
+//
+// applied version recorded in obj.schema_version, a database created by
+// the old res/psql/tab.sql has no such table, its schema is version 1
+
+use postgres::Client;
+
+use log::info;
+
+use crate::error::*;
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    sql: &'static str,
+}
+
+// append only, never edit an applied migration
+const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "init",
+                sql: include_str!("migrate/0001_init.sql") },
+    Migration { version: 2, name: "removed",
+                sql: include_str!("migrate/0002_removed.sql") },
+];
+
+const SQL_VERSION_TAB: &str = "
+create table if not exists obj.schema_version (
+       version int primary key,
+       name text not null,
+       applied timestamptz not null default now()
+)";
+
+pub fn latest() -> i32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+// None for empty database, Some(0) for schema without version table
+pub fn version(client: &mut Client) -> Result<Option<i32>> {
+    let row = client.query_one(
+        "select to_regclass('obj.schema_version') is not null,
+                to_regclass('obj.anno') is not null", &[])?;
+
+    let (has_version, has_anno): (bool, bool) = (row.get(0), row.get(1));
+
+    if !has_version {
+        return Ok(if has_anno { Some(0) } else { None })
+    }
+
+    let row = client.query_one(
+        "select coalesce(max(version), 0) from obj.schema_version", &[])?;
+
+    Ok(Some(row.get(0)))
+}
+
+// refuse to work on a schema of other version
+pub fn check(client: &mut Client) -> Result<()> {
+    match version(client)? {
+        Some(v) if v == latest() => Ok(()),
+        Some(v) if v > latest() => err(&format!(
+            "database schema version {} is newer than supported {}, upgrade nephrite",
+            v, latest())),
+        Some(v) => err(&format!(
+            "database schema version {} is older than {}, run nep-index --migrate",
+            v, latest())),
+        None => err("database schema not found, run nep-init or nep-index --migrate"),
+    }
+}
+
+fn record(trans: &mut postgres::Transaction, m: &Migration) -> Result<()> {
+    trans.batch_execute(SQL_VERSION_TAB)?;
+    trans.execute("insert into obj.schema_version (version, name) values ($1, $2)",
+                  &[&m.version, &m.name])?;
+    Ok(())
+}
+
+// upgrade to latest, return (version before, migrations applied)
+pub fn migrate(client: &mut Client) -> Result<(Option<i32>, Vec<&'static Migration>)> {
+    let old = version(client)?;
+    let mut current = old.unwrap_or(0);
+
+    // newer than this build, refuse
+    if current > latest() { check(client)?; }
+
+    // old schema, same as version 1, only record it
+    if current == 0 && old.is_some() {
+        let mut trans = client.transaction()?;
+        record(&mut trans, &MIGRATIONS[0])?;
+        trans.commit()?;
+
+        info!("migrate: version 1 recorded for existing schema");
+        current = 1;
+    }
+
+    let mut res = vec![];
+
+    for m in MIGRATIONS.iter().filter(|m| m.version > current) {
+        info!("migrate: apply {} {}", m.version, m.name);
+
+        let mut trans = client.transaction()?;
+
+        // concurrent migrate wait here, then fail on version primary key
+        trans.batch_execute("select pg_advisory_xact_lock(hashtext('nephrite-migrate'))")?;
+        trans.batch_execute(m.sql)?;
+        record(&mut trans, m)?;
+        trans.commit()?;
+
+        res.push(m);
+    }
+
+    Ok((old, res))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_order() {
+        for (i, m) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(m.version, i as i32 + 1);
+        }
+    }
+}
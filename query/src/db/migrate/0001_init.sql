@@ -1,3 +1,71 @@
+-- 1: initial schema, from res/psql/{tab,trig,idx}.sql
+
+create schema if not exists  obj;
+
+create table obj.anno (
+       id bytea primary key,
+
+       -- parent id, is history parenet of the anno
+       pid bytea[] not null default '{}',
+
+       -- file id for anno, link to obj.file.id
+       fid bytea not null,
+
+       -- generated
+       obsolete bool not null default true,
+       modified timestamptz not null default now()
+);
+
+create table obj.file (
+       id bytea primary key,
+
+       -- generated, default value should match aid
+       obsolete bool not null default true,
+
+       -- inverse of 'fid', ref to anno, auto update by trigger, empty for anno
+       aid bytea[] not null default '{}'
+);
+
+-- multiple meta for 
+create table obj.doc (
+       id bytea not null, -- id of anno or file
+       fid bytea, -- file id, when id point to anno
+       attr jsonb not null default '{}' -- NOTE: full content discarded
+);
+
+create table obj.fts (
+       id bytea not null,
+       fid bytea, -- file id
+       rel int8 not null default 0, -- offset
+       doc tsvector not null default ''
+);
+
+
+create schema if not exists sel;
+
+
+create table sel.tmp (
+  sel text not null,
+  id bytea not null,
+  primary key(sel, id)
+);
+
+create table sel.pers (
+  sel text not null,
+  id bytea not null,
+  primary key (sel, id)
+);
+
+create schema if not exists log;
+
+--create extension pg_trgm;
+--create extension intarray;
+
+--create index on m2 (oid);
+--create index ON m2 using gin (kv gin_trgm_ops, kn gin_trgm_ops);
+
+set local search_path to public, log, sel, obj;
+
 -- trigger
 -- 1. anno is immutable(except obsolete filed)
 -- 2. aid attr & doc is updatable for file
@@ -8,8 +76,6 @@
 --     * update ref-ed file, set aid to all anno with fid is oid and not obsolete
 --     * update ref-ed file, when has any aid, mark obsolete false, else true
 
-drop table if exists log.dat_op;
-
 create table log.dat_op (op text, id bytea);
 
 CREATE OR REPLACE FUNCTION obj.anno_trig_func() RETURNS TRIGGER AS $body$
@@ -35,12 +101,10 @@ BEGIN
         return NEW;
     end if;
 
-
     if (TG_OP = 'DELETE') and (OLD.obsolete = False) THEN
         raise exception 'can not delete non obsolete field';
     end if;
 
-
     if (TG_OP = 'INSERT') THEN
         update obj.anno as d1
             set obsolete = exists (select id from obj.anno
@@ -65,7 +129,6 @@ BEGIN
 end;
 $body$ LANGUAGE plpgsql;
 
-
 CREATE OR REPLACE FUNCTION obj.file_trig_func() RETURNS TRIGGER AS $body$
 BEGIN
     IF (TG_OP = 'UPDATE') THEN
@@ -84,12 +147,10 @@ BEGIN
         return NEW;
     end if;
 
-
     if (TG_OP = 'DELETE') and (OLD.obsolete = False) THEN
         raise exception 'can not delete non obsolete field';
     end if;
 
-
     if (TG_OP = 'INSERT') THEN
         update obj.file as d1
             set aid = array(select id from obj.anno
@@ -105,8 +166,6 @@ BEGIN
 end;
 $body$ LANGUAGE plpgsql;
 
-
-
 drop trigger if exists obj_anno_trig on obj.anno;
 create trigger obj_anno_trig
     after insert or update or delete ON obj.anno
@@ -117,7 +176,6 @@ create trigger obj_anno_trig1
     before insert or update ON obj.anno
     for each row execute procedure obj.anno_trig_func1();
 
-
 drop trigger if exists obj_file_trig on obj.file;
 create trigger obj_file_trig
     after insert or update or delete ON obj.file
@@ -153,7 +211,6 @@ BEGIN
 end;
 $body$ LANGUAGE plpgsql;
 
-
 drop trigger if exists obj_doc_trig on obj.doc;
 create trigger obj_doc_trig
     after insert or update or delete ON obj.doc
@@ -163,3 +220,29 @@ drop trigger if exists obj_fts_trig on obj.fts;
 create trigger obj_fts_trig
     after insert or update or delete ON obj.fts
     for each row execute procedure obj.up_fid_func();
+
+
+create index on obj.doc (fid);
+create index on obj.doc (id);
+
+create index on obj.fts (fid);
+create index on obj.fts (id);
+
+create index on obj.fts using gin(doc);
+create index on obj.doc using gin(attr);
+
+-- TODO
+--create index on obj.doc using gin(attr -> 'tag');
+
+create index on obj.doc ((attr ->> 'name'));
+
+create index on obj.file (id, obsolete);
+
+create index on obj.anno (id, obsolete);
+create index on obj.anno using gin(pid);
+create index on obj.anno (fid);
+
+create index on obj.anno (id, modified);
+
+create index on sel.tmp(id, sel);
+create index on sel.pers(id, sel);
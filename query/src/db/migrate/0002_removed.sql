@@ -0,0 +1,46 @@
+-- 2: tombstone anno, written by nep-rm, make whole chain obsolete
+
+alter table obj.anno add column removed bool not null default false;
+
+CREATE OR REPLACE FUNCTION obj.anno_trig_func() RETURNS TRIGGER AS $body$
+BEGIN
+    IF (TG_OP = 'UPDATE') THEN
+         IF (OLD.id != NEW.id) THEN
+             RAISE EXCEPTION 'id should not change';
+         END IF;
+
+       -- for ANNO
+        IF (OLD.fid != NEW.fid) OR
+           (OLD.pid != NEW.pid) OR
+           (OLD.removed != NEW.removed) THEN
+             raise exception 'only obsolete field for anno is mutable';
+        END IF;
+
+        if (OLD.obsolete != NEW.obsolete) then
+           update obj.file as d1
+             set aid = array(select id from obj.anno
+                                 where fid = d1.id and not obsolete)
+             where id = NEW.fid;
+        end if;
+
+        return NEW;
+    end if;
+
+    if (TG_OP = 'DELETE') and (OLD.obsolete = False) THEN
+        raise exception 'can not delete non obsolete field';
+    end if;
+
+    if (TG_OP = 'INSERT') THEN
+        -- tombstone is never current, so is its parent
+        update obj.anno as d1
+            set obsolete = NEW.removed or exists (select id from obj.anno
+                                                  where NEW.id = any(pid))
+            where id = NEW.id;
+        update obj.anno set obsolete = True where id = any(NEW.pid);
+
+        return NEW;
+    end if;
+
+    return NULL;
+end;
+$body$ LANGUAGE plpgsql;
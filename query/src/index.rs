@@ -5,6 +5,8 @@ use log::debug;
 use nephrite4_common::{conf, store};
 use nephrite4_common::proj;
 use nephrite4_common::util;
+use nephrite4_common::output;
+use nephrite4_common::error as cerr;
 
 use postgres::Client;
 
@@ -32,19 +34,20 @@ fn id2ref(id: &Id) -> &[u8] {
     &id[0..]
 }
 
-fn oid_exist_(client: &mut Client, id: &Id) -> Result<bool> {
-    let id_ref: &[u8] = &id[..];
-    for _row in client.query("select id from obj.anno where id = $1",
-                            &[&id_ref])? {
-        return Ok(true)
-    }
+// ids already imported, as anno or file, in one query
+fn oids_exist_(client: &mut Client, ids: &[Id]) -> Result<BTreeSet<Id>> {
+    let id_refs: Vec<&[u8]> = ids.iter().map(id2ref).collect();
+    let mut res = BTreeSet::new();
 
-    for _row in client.query("select id from obj.file where id = $1",
-                            &[&id_ref])? {
-        return Ok(true)
+    for row in client.query(
+        concat!("select id from obj.anno where id = any($1) ",
+                "union select id from obj.file where id = any($1)"),
+        &[&id_refs])? {
+        let id: Vec<u8> = row.get(0);
+        res.insert(util::to_id(&id));
     }
 
-    return Ok(false)
+    Ok(res)
 }
 
 fn _last_anno_(client: &mut Client) -> Result<Option<Id>> {
@@ -69,12 +72,13 @@ fn import_anno_(client: &mut Client,
         .map(|i| id2ref(i)).collect();
 
     trans.execute(
-        concat!("insert into obj.anno (id, pid, fid) ",
-                "values ($1, $2, $3) ",
+        concat!("insert into obj.anno (id, pid, fid, removed) ",
+                "values ($1, $2, $3, $4) ",
                 "on conflict (id) do nothing"),
         &[&id2ref(id),
           &pids,
-          &id2ref(&anno.fid)])?;
+          &id2ref(&anno.fid),
+          &anno.is_removed()])?;
 
     trans.execute(
         concat!("insert into obj.file (id) ",
@@ -206,6 +210,9 @@ pub struct Indexer {
     pub client: Client,
     pub tika: tika::Tika,
 
+    // NDJSON instead of progress message
+    pub json: bool,
+
     done_set: BTreeSet<Id>,
 }
 
@@ -217,7 +224,7 @@ impl Indexer {
 
         let done_set = BTreeSet::new();
 
-        Ok(Indexer { store, client, tika, done_set })
+        Ok(Indexer { store, client, tika, json: false, done_set })
     }
 
     pub fn is_done(&self, id: &Id) -> bool {
@@ -226,14 +233,18 @@ impl Indexer {
 
     // import single "file"
     pub fn import_file(&mut self, id: &Id) -> Result<()> {
-        let mut bup = self.store.spawn_bup_join(id)?;
+        let (rdr, join) = self.store.spawn_join(id)?;
 
-        let res: String;
-        {
-            let stdout = bup.stdout.take().unwrap();
-            res = self.tika.parse_from_fd(stdout)?;
+        let res = self.tika.parse_from_fd(rdr)?;
+
+        match join.join() {
+            Ok(Ok(_)) => (),
+            // NOTE: tika may stop before eof
+            Ok(Err(cerr::Error::IO(ref e)))
+                if e.kind() == io::ErrorKind::BrokenPipe => (),
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => return err("join thread panic"),
         }
-        bup.wait()?;
 
         let json = tika::tika_res(&res)?;
 
@@ -247,7 +258,8 @@ impl Indexer {
 
         import_anno_(&mut self.client, id, &anno)?;
 
-        if with_file {
+        // file content is indexed already by its parent
+        if with_file && !anno.is_removed() {
             self.import_file(&anno.fid)?;
         }
 
@@ -261,16 +273,61 @@ impl Indexer {
         let mut res = 0;
 
         for (cid, aid_set) in list.into_iter() {
-            println!("index changeset {}", &hex::encode(&cid[..5]));
-            for aid in aid_set {
-                if oid_exist_(&mut self.client, &aid)? {
-                    println!("  {} exist, skip", &hex::encode(&aid[..5]));
+            if !self.json {
+                println!("index changeset {}", &hex::encode(&cid[..5]));
+            }
+
+            let aids: Vec<Id> = aid_set.into_iter().collect();
+
+            // check whole changeset before import any of it
+            let missing: Vec<String> = self.store.objects_exist(&aids)?
+                .into_iter().zip(aids.iter())
+                .filter(|(found, _)| !found)
+                .map(|(_, aid)| hex::encode(&aid[..5]))
+                .collect();
+
+            if !missing.is_empty() {
+                return err(&format!("changeset {} incomplete, missing {}",
+                                    &hex::encode(&cid[..5]),
+                                    missing.join(", ")));
+            }
+
+            let exist = oids_exist_(&mut self.client, &aids)?;
+
+            for aid in aids {
+                if exist.contains(&aid) {
+                    if self.json {
+                        output::emit(serde_json::json!({
+                            "type": "anno", "cset": output::id(&cid),
+                            "aid": output::id(&aid), "state": "exist",
+                            "diverged": null}));
+                    }
+                    else {
+                        println!("  {} exist, skip", &hex::encode(&aid[..5]));
+                    }
                     continue;
                 }
 
                 self.import_anno(&aid, true)?;
-                println!("  {} imported", &hex::encode(&aid[..5]));
                 res += 1;
+
+                // edited on other host too
+                let diverged = self.store.anno_diverged(&aid)?;
+
+                if self.json {
+                    output::emit(serde_json::json!({
+                        "type": "anno", "cset": output::id(&cid),
+                        "aid": output::id(&aid), "state": "imported",
+                        "diverged": diverged.as_ref().map(output::id)}));
+                    continue;
+                }
+
+                println!("  {} imported", &hex::encode(&aid[..5]));
+
+                if let Some(tip) = diverged {
+                    println!("  {} diverged from local {}, run nep-merge",
+                             &hex::encode(&aid[..5]), &hex::encode(&tip[..5]));
+                }
             }
 
             self.store.git_update_ref(&store::ref_local(&cset), &cid)?;
@@ -280,23 +337,20 @@ impl Indexer {
     }
 
     pub fn index_cset_all(&mut self) -> Result<usize> {
-        let refs: Vec<String> =
-            self.store.git_show_ref_all()?.keys()
-            .filter(|x| x.starts_with("refs/remotes/") &&
-                    x.ends_with("/localhost"))
-            .map(|x| x
-                 .strip_prefix("refs/remotes/").unwrap()
-                 .strip_suffix("/localhost").unwrap()
-                 .to_string())
-            .collect();
+        let refs = self.store.cset_all()?;
 
         debug!("index_cset_all: all refs {:?}", refs);
 
         let mut res = 0;
         for r in refs {
-            println!("index changeset for '{}'", &r);
+            if !self.json {
+                println!("index changeset for '{}'", &r);
+            }
+
             match self.index_cset(&r) {
                 Ok(x) => res += x,
+                Err(e) if self.json => output::emit(serde_json::json!({
+                    "type": "error", "cset": r, "msg": e.to_string()})),
                 Err(_) => ()
             }
         }
@@ -3,7 +3,7 @@ use nephrite4_common::conf;
 use j4rs::{Instance, InvocationArg, ClasspathEntry, Jvm, JvmBuilder};
 
 use log::{debug, info};
-use std::{collections::BTreeMap, env, fs::File, io::Write, os::unix::prelude::AsRawFd, process::Command};
+use std::{collections::BTreeMap, fs::File, io::Write, os::unix::prelude::AsRawFd, process::Command};
 
 use crate::error::*;
 
@@ -35,17 +35,11 @@ fn ja_bool(i: bool) -> InvocationArg {
     InvocationArg::try_from(i).unwrap().into_primitive().unwrap()
 }
 
-fn j4rs_tmp_dir() -> Result<String> {
-    let base = env::var("XDG_RUNTIME_DIR").unwrap_or("/tmp".into());
-
-    Ok(base + "/nephrite")
-}
-
 impl Tika {
     pub fn new(conf: &conf::Conf) -> Result<Tika> {
-        let jar = conf.tika_jar();
+        let jar = conf.tika_jar()?;
 
-        let tmp_dir = j4rs_tmp_dir()?;
+        let tmp_dir = conf.tika_tmp_dir();
 
         info!("init...");
 